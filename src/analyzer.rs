@@ -1,293 +1,1431 @@
-use crate::parser::AnalyzedDoc;
-use crate::span::Span;
-use crate::symbol::{SemanticIndex, Symbol, SymbolDetails, SymbolKind};
-use powdr_ast::analyzed::Analyzed;
-use powdr_ast::asm_analysis::{AnalysisASMFile, CallableSymbol};
-use powdr_ast::parsed::asm::AbsoluteSymbolPath;
-use tower_lsp::Client;
-use tower_lsp::lsp_types::MessageType;
+use crate::panic::catch_panic;
+use crate::parser::{AnalyzedDoc, ParsedAst};
+use crate::span::{Span, code_tokens};
+use crate::symbol::{
+    MachineSummary, SemanticIndex, Symbol, SymbolDetails, SymbolId, SymbolKind, SymbolUse, UseKind,
+};
+use powdr_ast::analyzed::{
+    Analyzed, FunctionValueDefinition, PolynomialType, Symbol as PilSymbol,
+    SymbolKind as PilSymbolKind, TypeDeclaration,
+};
+use powdr_ast::asm_analysis::{
+    AnalysisASMFile, CallableSymbol, FunctionStatement, FunctionSymbol, Item, Machine, RegisterTy,
+};
+use powdr_ast::parsed::asm::{
+    ASMModule, ASMProgram, AbsoluteSymbolPath, Machine as ParsedMachine, MachineStatement, Module,
+    ModuleStatement, SymbolValue,
+};
+use powdr_ast::parsed::types::{Type, TypeScheme};
+use powdr_ast::parsed::visitor::AllChildren;
+use powdr_ast::parsed::{
+    BinaryOperation, BinaryOperator, Expression, PILFile, PilStatement, PolynomialName,
+    SourceReference, UnaryOperation, UnaryOperator,
+};
+use powdr_parser_util::SourceRef;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use tracing::{debug, error};
 
+/// Index fragments of the machines of a document, kept between indexing runs
+/// of the same document. A machine whose text, position and analysis did not
+/// change is not indexed again.
+#[derive(Default)]
+pub struct MachineUnits {
+    units: HashMap<String, MachineUnit>,
+    /// Machines whose unit the last run reused.
+    reused: BTreeSet<String>,
+}
+
+impl MachineUnits {
+    #[cfg(test)]
+    pub(crate) fn reused(&self, machine: &str) -> bool {
+        self.reused.contains(machine)
+    }
+}
+
+struct MachineUnit {
+    start: usize,
+    text: String,
+    /// The printed analysis of the machine, which covers what its text does
+    /// not, e.g. how imports resolved.
+    analyzed: String,
+    index: SemanticIndex,
+    machine_uses: Vec<(AbsoluteSymbolPath, Span)>,
+}
+
+/// Indexes an analyzed document. `parsed` is the document's AST before import
+/// resolution, which keeps what the analysis drops, e.g. `use` statements.
 pub fn build_semantic_index<T>(
     doc: &AnalyzedDoc<T>,
+    parsed: Option<&ParsedAst>,
     source_text: &str,
-) -> (SemanticIndex, Vec<String>) {
-    let mut index = SemanticIndex::new();
+) -> SemanticIndex {
+    build_semantic_index_reusing(doc, parsed, source_text, &mut MachineUnits::default())
+}
 
-    let errors = match doc {
-        AnalyzedDoc::ASM(asm) => analyze_asm(asm, &mut index, source_text),
-        AnalyzedDoc::PIL(pil) => analyze_pil(pil, &mut index, source_text),
-    };
+/// Like `build_semantic_index`, reusing and updating the `units` of the
+/// document's previous index.
+pub fn build_semantic_index_reusing<T>(
+    doc: &AnalyzedDoc<T>,
+    parsed: Option<&ParsedAst>,
+    source_text: &str,
+    units: &mut MachineUnits,
+) -> SemanticIndex {
+    // A bug in the indexer should cost the document its symbols, not the server.
+    let index = catch_panic(|| {
+        let mut index = SemanticIndex::new();
+        match doc {
+            AnalyzedDoc::ASM(asm) => {
+                let program = match parsed {
+                    Some(ParsedAst::ASM(program)) => Some(program),
+                    _ => None,
+                };
+                analyze_asm(asm, program, &mut index, source_text, units)
+            }
+            AnalyzedDoc::PIL(pil) => {
+                let file = match parsed {
+                    Some(ParsedAst::PIL(file)) => Some(file),
+                    _ => None,
+                };
+                analyze_pil(pil, file, &mut index, source_text)
+            }
+        };
+        collect_docs(&mut index, source_text);
+        index
+    })
+    .unwrap_or_else(|message| {
+        error!(%message, "indexing panicked");
+        *units = MachineUnits::default();
+        SemanticIndex::new()
+    });
 
-    (index, errors)
-}
-struct PositionTracker<'a> {
-    text: &'a str,
-    current_pos: usize,
+    debug!(symbols = index.symbols.len(), "built semantic index");
+
+    index
 }
+/// Keywords that can precede the name in a declaration.
+const DECLARATION_KEYWORDS: &[&str] = &[
+    "machine",
+    "operation",
+    "function",
+    "instr",
+    "reg",
+    "mod",
+    "let",
+    "pub",
+    "col",
+    "pol",
+    "witness",
+    "fixed",
+    "commit",
+    "constant",
+    "enum",
+    "struct",
+    "trait",
+    "namespace",
+];
 
-impl<'a> PositionTracker<'a> {
-    fn new(text: &'a str) -> Self {
-        Self {
-            text,
-            current_pos: 0,
-        }
-    }
-
-    // TODO: Check if this could come from the parser
-    // fn find_symbol_position(&mut self, symbol: &str) -> (Option<Span>, Vec<String>) {
-    //     let mut log_messages = Vec::new();
-    //     log_messages.push(format!("Searching for symbol: '{}'", symbol));
-    //     log_messages.push(format!(
-    //         "Starting search from position: {}",
-    //         self.current_pos
-    //     ));
-
-    //     if let Some(pos) = self.text[self.current_pos..].find(symbol) {
-    //         let start = self.current_pos + pos;
-    //         let end = start + symbol.len();
-    //         self.current_pos = end;
-
-    //         // TODO: Remove log
-    //         let context = self
-    //             .text
-    //             .get(start.saturating_sub(10)..end.saturating_add(10))
-    //             .unwrap_or("");
-    //         log_messages.push(format!("Found symbol at span {:?}", start..end));
-    //         log_messages.push(format!("Context: '...{}...'", context));
-
-    //         (Some(start..end), log_messages)
-    //     } else {
-    //         log_messages.push(format!("Symbol not found in remaining text"));
-    //         (None, log_messages)
-    //     }
-    // }
-
-    fn find_symbol_positions(&mut self, symbol: &str) -> (Vec<Span>, Vec<String>) {
-        let mut log_messages = Vec::new();
-        let mut positions = Vec::new();
-        let mut search_pos = self.current_pos;
-
-        log_messages.push(format!(
-            "Searching for all occurrences of symbol: '{}'",
-            symbol
-        ));
-        log_messages.push(format!("Starting search from position: {}", search_pos));
-
-        while let Some(pos) = self.text[search_pos..].find(symbol) {
-            let abs_start = search_pos + pos;
-            let abs_end = abs_start + symbol.len();
-
-            // Check for word boundaries using proper identifier rules
-            let is_valid_start =
-                abs_start == 0 || !is_identifier_char(self.text.as_bytes()[abs_start - 1] as char);
-            let is_valid_end = abs_end >= self.text.len()
-                || !is_identifier_char(self.text.as_bytes()[abs_end] as char);
-
-            // Check if we're inside a comment
-            let line_start = self.text[..abs_start].rfind('\n').unwrap_or(0);
-            let line_content = &self.text[line_start..abs_start];
-            let is_in_comment = line_content.trim_start().starts_with("//");
-
-            if !is_in_comment && is_valid_start && is_valid_end {
-                positions.push(abs_start..abs_end);
-                log_messages.push(format!(
-                    "Found valid occurrence at span {:?}",
-                    abs_start..abs_end
-                ));
-            } else {
-                log_messages.push(format!(
-                    "Skipping occurrence at span {:?} (in_comment: {}, valid_start: {}, valid_end: {})",
-                    abs_start..abs_end,
-                    is_in_comment,
-                    is_valid_start,
-                    is_valid_end
-                ));
-            }
+/// Attaches the `///` or `//` comment lines directly above a declaration to its symbol.
+fn collect_docs(index: &mut SemanticIndex, source_text: &str) {
+    let mut docs = Vec::new();
 
-            search_pos = abs_end;
+    let declarations = index
+        .symbols
+        .iter()
+        .filter(|(id, _)| !index.declarations.contains_key(id));
+    for (id, symbol) in declarations {
+        let line_start = source_text[..symbol.span.start]
+            .rfind('\n')
+            .map_or(0, |pos| pos + 1);
+        let prefix = source_text[line_start..symbol.span.start].split_whitespace();
+        let mut keywords = 0;
+        let mut is_declaration = true;
+        for word in prefix {
+            keywords += 1;
+            is_declaration &= DECLARATION_KEYWORDS.contains(&word);
+        }
+        if keywords == 0 || !is_declaration {
+            continue;
         }
 
-        if positions.is_empty() {
-            log_messages.push("No occurrences found".to_string());
-        } else {
-            log_messages.push(format!("Found {} occurrences", positions.len()));
+        let mut lines: Vec<&str> = source_text[..line_start]
+            .lines()
+            .rev()
+            .map(str::trim)
+            .take_while(|line| line.starts_with("//"))
+            .map(|line| {
+                let line = line.trim_start_matches('/');
+                line.strip_prefix(' ').unwrap_or(line)
+            })
+            .collect();
+        if lines.is_empty() {
+            continue;
         }
+        lines.reverse();
+        docs.push((*id, lines.join("\n")));
+    }
 
-        (positions, log_messages)
+    for (id, doc) in docs {
+        index.add_doc(id, doc);
     }
 }
 
-fn is_identifier_char(c: char) -> bool {
+pub(crate) fn is_identifier_char(c: char) -> bool {
     c.is_alphanumeric() || c == '_' || c == ':' // TODO: Too naive
 }
 
-fn analyze_asm(asm: &AnalysisASMFile, index: &mut SemanticIndex, source_text: &str) -> Vec<String> {
-    let mut tracker = PositionTracker::new(source_text);
-    let mut log_messages = Vec::new();
+/// Indexes an asm document. The analysis drops the source references of
+/// machines, modules, imports and constants, so the parsed file drives the
+/// walk and the analyzed items provide the details. Every span comes from the
+/// source reference of the statement declaring or using the symbol.
+fn analyze_asm(
+    asm: &AnalysisASMFile,
+    program: Option<&ASMProgram>,
+    index: &mut SemanticIndex,
+    source_text: &str,
+    units: &mut MachineUnits,
+) {
+    let Some(program) = program else {
+        return;
+    };
 
-    for (name, machine) in asm.machines() {
-        let (spans, messages) = tracker
-            .find_symbol_positions(&name.relative_to(&AbsoluteSymbolPath::default()).to_string());
-        log_messages.extend(messages);
+    let mut indexer = AsmIndexer {
+        asm,
+        text: source_text,
+        locator: ItemLocator::new(source_text),
+        machines: BTreeMap::new(),
+        machine_uses: Vec::new(),
+        previous: std::mem::take(&mut units.units),
+        units: HashMap::new(),
+        reused: BTreeSet::new(),
+    };
+    indexer.index_module(&program.main, &AbsoluteSymbolPath::default(), index);
+    indexer.index_machine_uses(index);
+    debug!(
+        machines = indexer.units.len(),
+        reused = indexer.reused.len(),
+        "indexed machines"
+    );
+    units.units = indexer.units;
+    units.reused = indexer.reused;
+}
 
-        let short_name = name.clone().pop().unwrap(); // TODO: Improve this
-        for span in spans {
-            index.add_symbol(Symbol {
-                kind: SymbolKind::Machine,
-                name: name.to_string(),
-                span: span.clone(),
-                details: SymbolDetails::Machine {
-                    degree: Some(machine.degree.clone().into()),
-                },
-            });
+/// A symbol declared in a scope, which is also added at its uses in that scope.
+struct Declared {
+    name: String,
+    span: Span,
+    kind: SymbolKind,
+    details: SymbolDetails,
+}
 
-            // TODO: Deduplicate this
-            index.add_symbol(Symbol {
-                kind: SymbolKind::Machine,
-                name: short_name.to_string(),
-                span,
-                details: SymbolDetails::Machine {
-                    degree: Some(machine.degree.clone().into()),
-                },
-            });
+impl Declared {
+    /// The symbol at `span`, the declaration or a use.
+    fn at(&self, span: Span) -> Symbol {
+        Symbol {
+            kind: self.kind.clone(),
+            name: self.name.clone(),
+            span,
+            details: self.details.clone(),
         }
+    }
+}
 
-        for callable in &machine.callable {
-            let (spans, messages) = tracker.find_symbol_positions(&callable.name);
-            log_messages.extend(messages);
-
-            for span in spans {
-                match callable.symbol {
-                    CallableSymbol::Function(func) => {
-                        index.add_symbol(Symbol {
-                            kind: SymbolKind::Callable,
-                            name: callable.name.to_string(),
-                            span,
-                            details: SymbolDetails::Callable {
-                                inputs: func
-                                    .params
-                                    .inputs
-                                    .iter()
-                                    .map(|p| p.to_string())
-                                    .collect::<Vec<_>>()
-                                    .join(", "),
-                                outputs: func
-                                    .params
-                                    .outputs
-                                    .iter()
-                                    .map(|p| p.to_string())
-                                    .collect::<Vec<_>>()
-                                    .join(", "),
-                            },
-                        });
+struct AsmIndexer<'a> {
+    asm: &'a AnalysisASMFile,
+    text: &'a str,
+    locator: ItemLocator<'a>,
+    /// Machines declared in this file with the id of their declaration, by path.
+    machines: BTreeMap<AbsoluteSymbolPath, (SymbolId, SymbolDetails)>,
+    /// Submachine types, resolved to machine paths, where they are written.
+    machine_uses: Vec<(AbsoluteSymbolPath, Span)>,
+    /// Machine units of the previous index, and those of this one by machine path.
+    previous: HashMap<String, MachineUnit>,
+    units: HashMap<String, MachineUnit>,
+    reused: BTreeSet<String>,
+}
+
+impl AsmIndexer<'_> {
+    fn index_module(
+        &mut self,
+        module: &ASMModule,
+        path: &AbsoluteSymbolPath,
+        index: &mut SemanticIndex,
+    ) {
+        let first_use = index.uses.len();
+        let mut scope = Vec::new();
+
+        for statement in &module.statements {
+            let ModuleStatement::SymbolDefinition(definition) = statement else {
+                continue;
+            };
+            let name = &definition.name;
+            let item_path = path.with_part(name);
+
+            match &definition.value {
+                SymbolValue::Machine(machine) => {
+                    let Some(span) = self.locator.declaration("machine", name) else {
+                        continue;
+                    };
+                    add_use(index, name, span.start, UseKind::Declaration);
+                    let end = machine
+                        .statements
+                        .iter()
+                        .map(|statement| statement.source_reference().end)
+                        .max();
+                    if let Some(end) = end {
+                        self.locator.skip_to(end);
                     }
-                    CallableSymbol::Operation(op) => {
-                        index.add_symbol(Symbol {
-                            kind: SymbolKind::Callable,
-                            name: callable.name.to_string(),
-                            span,
-                            details: SymbolDetails::Callable {
-                                inputs: op
-                                    .params
-                                    .inputs
-                                    .iter()
-                                    .map(|p| p.to_string())
-                                    .collect::<Vec<_>>()
-                                    .join(", "),
-                                outputs: op
-                                    .params
-                                    .outputs
-                                    .iter()
-                                    .map(|p| p.to_string())
-                                    .collect::<Vec<_>>()
-                                    .join(", "),
-                            },
-                        });
+                    let Some(Item::Machine(analyzed)) = self.asm.items.get(&item_path) else {
+                        continue;
+                    };
+
+                    let details = SymbolDetails::Machine {
+                        degree: Some(analyzed.degree.clone().into()),
+                        summary: summarize_machine(analyzed),
+                    };
+                    let ids: Vec<_> = [item_path.to_string(), name.clone()]
+                        .into_iter()
+                        .map(|symbol_name| {
+                            index.add_symbol(Symbol {
+                                kind: SymbolKind::Machine,
+                                name: symbol_name,
+                                span: span.clone(),
+                                details: details.clone(),
+                            })
+                        })
+                        .collect();
+                    self.machines.insert(item_path.clone(), (ids[0], details));
+                    let region = span.start..end.unwrap_or(span.end).max(span.end);
+                    self.index_machine_unit(&item_path, region, analyzed, machine, index);
+                }
+                SymbolValue::Module(module) => {
+                    let Some(span) = self.locator.declaration("mod", name) else {
+                        continue;
+                    };
+                    add_use(index, name, span.start, UseKind::Declaration);
+                    index.add_symbol(Symbol {
+                        kind: SymbolKind::Module,
+                        name: name.clone(),
+                        span,
+                        details: SymbolDetails::Module {
+                            path: item_path.to_string(),
+                        },
+                    });
+                    if let Module::Local(inner) = module {
+                        self.index_module(inner, &item_path, index);
                     }
                 }
+                SymbolValue::Import(import) => {
+                    let Some(span) = self.locator.import(name) else {
+                        continue;
+                    };
+                    add_use(index, name, span.start, UseKind::Declaration);
+                    scope.push(Declared {
+                        name: name.clone(),
+                        span,
+                        kind: SymbolKind::Import,
+                        details: SymbolDetails::Import {
+                            target: import.path.to_string(),
+                        },
+                    });
+                }
+                SymbolValue::Expression(expression) => {
+                    let Some(span) = self.locator.declaration("let", name) else {
+                        continue;
+                    };
+                    add_use(index, name, span.start, UseKind::Declaration);
+                    self.locator.skip_to(expression.e.source_reference().end);
+                    // The analyzed item carries the inferred type.
+                    let typed = match self.asm.items.get(&item_path) {
+                        Some(Item::Expression(typed)) => typed,
+                        _ => expression,
+                    };
+                    scope.push(Declared {
+                        name: name.clone(),
+                        span,
+                        kind: SymbolKind::Constant,
+                        details: SymbolDetails::Constant {
+                            type_info: typed.type_scheme.as_ref().map(|ty| ty.to_string()),
+                            value: Some(typed.e.to_string()),
+                        },
+                    });
+                }
+                _ => {}
             }
         }
 
-        for register in &machine.registers {
-            let (spans, messages) = tracker.find_symbol_positions(&register.name);
-            log_messages.extend(messages);
+        let ids: Vec<_> = scope
+            .iter()
+            .map(|declared| index.add_symbol(declared.at(declared.span.clone())))
+            .collect();
+        self.index_module_uses(module, &scope, &ids, index);
+        index.scope_uses(first_use, &path.to_string());
+    }
 
-            for span in spans {
-                index.add_symbol(Symbol {
-                    kind: SymbolKind::Register,
-                    name: register.name.to_string(),
+    /// Adds the imports and constants of a module where the module's machines
+    /// and constants refer to them. Names are matched as written, before the
+    /// importer resolved them, and only within the declaring module.
+    fn index_module_uses(
+        &self,
+        module: &ASMModule,
+        scope: &[Declared],
+        ids: &[SymbolId],
+        index: &mut SemanticIndex,
+    ) {
+        let find = |name: &str, kind: SymbolKind| {
+            scope
+                .iter()
+                .position(|declared| declared.kind == kind && declared.name == name)
+        };
+        let mut uses = Vec::new();
+
+        for statement in &module.statements {
+            let ModuleStatement::SymbolDefinition(definition) = statement else {
+                continue;
+            };
+            let references: Vec<&Expression> = match &definition.value {
+                SymbolValue::Machine(machine) => {
+                    for statement in &machine.statements {
+                        // `Type name(args);` names its type outside of any expression.
+                        let MachineStatement::Submachine(source, ty, _, _) = statement else {
+                            continue;
+                        };
+                        let ty = ty.to_string();
+                        let first = ty.split("::").next().unwrap_or(&ty);
+                        if let (Some(import), Some(span)) = (
+                            find(first, SymbolKind::Import),
+                            name_in(source, first, self.text),
+                        ) {
+                            uses.push((import, span));
+                        }
+                    }
+                    machine
+                        .statements
+                        .iter()
+                        .flat_map(|statement| statement.all_children())
+                        .collect()
+                }
+                SymbolValue::Expression(expression) => expression.e.all_children().collect(),
+                _ => continue,
+            };
+
+            for expression in references {
+                let Expression::Reference(source, reference) = expression else {
+                    continue;
+                };
+                let path = reference.path.to_string();
+                let first = path.split("::").next().unwrap_or(&path);
+                let declared =
+                    find(first, SymbolKind::Import).or_else(|| find(&path, SymbolKind::Constant));
+                if let Some(position) = declared.filter(|_| is_local(source, self.text)) {
+                    let start = source.start;
+                    uses.push((position, start..start + scope[position].name.len()));
+                }
+            }
+        }
+
+        for (position, span) in uses {
+            let declared = &scope[position];
+            add_use(index, &declared.name, span.start, UseKind::Read);
+            index.add_occurrence(declared.at(span), ids[position]);
+        }
+    }
+
+    /// Indexes a machine, or reuses its unit from the previous index if
+    /// neither the text of the machine, its position nor its analysis changed.
+    fn index_machine_unit(
+        &mut self,
+        path: &AbsoluteSymbolPath,
+        region: Span,
+        machine: &Machine,
+        parsed: &ParsedMachine,
+        index: &mut SemanticIndex,
+    ) {
+        let key = path.to_string();
+        let text = self.text.get(region.clone()).unwrap_or_default();
+        let analyzed = machine.to_string();
+
+        let unit = match self.previous.remove(&key) {
+            Some(unit)
+                if unit.start == region.start && unit.text == text && unit.analyzed == analyzed =>
+            {
+                self.reused.insert(key.clone());
+                unit
+            }
+            _ => {
+                let first_machine_use = self.machine_uses.len();
+                let mut fragment = SemanticIndex::new();
+                self.index_machine(path, machine, parsed, &mut fragment);
+                MachineUnit {
+                    start: region.start,
+                    text: text.to_string(),
+                    analyzed,
+                    index: fragment,
+                    machine_uses: self.machine_uses.split_off(first_machine_use),
+                }
+            }
+        };
+
+        index.extend(&unit.index);
+        self.machine_uses.extend(unit.machine_uses.iter().cloned());
+        self.units.insert(key, unit);
+    }
+
+    /// Indexes the symbols declared in a machine, at their declaration and at
+    /// every use inside the machine.
+    fn index_machine(
+        &mut self,
+        path: &AbsoluteSymbolPath,
+        machine: &Machine,
+        parsed: &ParsedMachine,
+        index: &mut SemanticIndex,
+    ) {
+        let text = self.text;
+        let first_use = index.uses.len();
+        let mut declared = Vec::new();
+
+        for register in &machine.registers {
+            if let Some(span) = name_in(&register.source, &register.name, text) {
+                declared.push(Declared {
+                    name: register.name.clone(),
                     span,
+                    kind: SymbolKind::Register,
                     details: SymbolDetails::Register {
                         type_info: register.ty.to_string(),
                     },
                 });
             }
         }
-    }
 
-    log_messages
-}
-fn analyze_pil<T>(pil: &Analyzed<T>, index: &mut SemanticIndex, source_text: &str) -> Vec<String> {
-    let mut tracker = PositionTracker::new(source_text);
-    let mut log_messages = Vec::new();
+        for instr in &machine.instructions {
+            let params = &instr.instruction.params;
+            if let Some(span) = name_in(&instr.source, &instr.name, text) {
+                declared.push(Declared {
+                    name: instr.name.clone(),
+                    span,
+                    kind: SymbolKind::Instruction,
+                    details: SymbolDetails::Callable {
+                        inputs: join(&params.inputs),
+                        outputs: join(&params.outputs),
+                    },
+                });
+            }
+        }
+
+        for callable in &machine.callable {
+            let (kind, source, params) = match callable.symbol {
+                CallableSymbol::Function(func) => {
+                    (SymbolKind::Function, &func.source, &func.params)
+                }
+                CallableSymbol::Operation(op) => (SymbolKind::Operation, &op.source, &op.params),
+            };
+            if let Some(span) = name_in(source, callable.name, text) {
+                declared.push(Declared {
+                    name: callable.name.to_string(),
+                    span,
+                    kind,
+                    details: SymbolDetails::Callable {
+                        inputs: join(&params.inputs),
+                        outputs: join(&params.outputs),
+                    },
+                });
+            }
+        }
 
-    for (name, _def) in &pil.definitions {
-        let (spans, messages) = tracker.find_symbol_positions(name);
-        log_messages.extend(messages);
+        // The analysis keeps no source of submachine declarations.
+        for statement in &parsed.statements {
+            let MachineStatement::Submachine(source, ty, name, _) = statement else {
+                continue;
+            };
+            let Some(submachine) = machine
+                .submachines
+                .iter()
+                .find(|submachine| submachine.name == *name)
+            else {
+                continue;
+            };
+            let Some(ty_span) = self.locator.path_in(source, &ty.to_string()) else {
+                continue;
+            };
+            let after_ty = SourceRef {
+                start: ty_span.end,
+                ..source.clone()
+            };
+            let Some(span) = name_in(&after_ty, name, text) else {
+                continue;
+            };
 
-        for span in spans {
-            index.add_symbol(Symbol {
-                kind: SymbolKind::Definition,
+            self.machine_uses.push((submachine.ty.clone(), ty_span));
+            add_use(index, name, span.start, UseKind::Declaration);
+            // `Arith arith(byte2);` uses the instances it is constructed with.
+            for arg in &submachine.args {
+                collect_reads(arg, index);
+            }
+            declared.push(Declared {
                 name: name.clone(),
                 span,
-                details: SymbolDetails::Definition,
+                kind: SymbolKind::Submachine,
+                details: SymbolDetails::Submachine {
+                    machine_type: submachine.ty.to_string(),
+                    arguments: join(&submachine.args),
+                },
             });
         }
+
+        for statement in &machine.pil {
+            let Some((kind, names)) = declared_columns(statement) else {
+                continue;
+            };
+            let declaration = statement
+                .to_string()
+                .lines()
+                .next()
+                .unwrap_or("")
+                .to_string();
+            for name in names {
+                if let Some(span) = name_in(statement.source_reference(), &name, text) {
+                    declared.push(Declared {
+                        name,
+                        span,
+                        kind: kind.clone(),
+                        details: SymbolDetails::Column {
+                            declaration: declaration.clone(),
+                        },
+                    });
+                }
+            }
+        }
+
+        let links = machine
+            .instructions
+            .iter()
+            .flat_map(|instr| &instr.instruction.links)
+            .chain(&machine.links);
+        for link in links {
+            // Links are not named, the `instance.operation` they call is indexed.
+            let target = format!("{}.{}", link.to.instance, link.to.callable);
+            if let Some(span) = name_in(&link.source, &target, text) {
+                index.add_symbol(Symbol {
+                    kind: SymbolKind::Link,
+                    name: target.clone(),
+                    span,
+                    details: SymbolDetails::Link {
+                        target,
+                        permutation: link.is_permutation,
+                    },
+                });
+            }
+        }
+
+        for callable in &machine.callable {
+            if let CallableSymbol::Function(func) = callable.symbol {
+                let function_first_use = index.uses.len();
+                collect_function_uses(func, index, text);
+                index_labels(callable.name, func, function_first_use, index, text);
+            }
+        }
+        collect_machine_uses(machine, index, text);
+
+        add_symbols(&declared, first_use, index);
+        index.scope_uses(first_use, &path.to_string());
     }
 
-    for (name, _decl) in &pil.public_declarations {
-        let (spans, messages) = tracker.find_symbol_positions(name);
-        log_messages.extend(messages);
+    /// Adds the machines of this file where submachine declarations name them
+    /// as their type.
+    fn index_machine_uses(&mut self, index: &mut SemanticIndex) {
+        for (path, span) in std::mem::take(&mut self.machine_uses) {
+            let Some((declaration, details)) = self.machines.get(&path) else {
+                continue;
+            };
+            // An imported alias is already indexed there.
+            if index.find_symbol_at_position(span.start).is_some() {
+                continue;
+            }
+            index.add_occurrence(
+                Symbol {
+                    kind: SymbolKind::Machine,
+                    name: path.to_string(),
+                    span,
+                    details: details.clone(),
+                },
+                *declaration,
+            );
+        }
+    }
+}
 
-        for span in spans {
-            index.add_symbol(Symbol {
-                kind: SymbolKind::Public,
-                name: name.clone(),
-                span,
-                details: SymbolDetails::Public,
-            });
+/// Adds each declared symbol at its declaration and at every use recorded
+/// since `first_use` that refers to it by name.
+fn add_symbols(declared: &[Declared], first_use: usize, index: &mut SemanticIndex) {
+    let mut spans: Vec<(usize, Span)> = Vec::new();
+    let mut seen = HashSet::new();
+    for symbol_use in &index.uses[first_use..] {
+        // Declarations are added with their symbol below.
+        if symbol_use.kind == UseKind::Declaration || !seen.insert(symbol_use.span.clone()) {
+            continue;
+        }
+        if let Some(position) = declared
+            .iter()
+            .position(|declared| declared.name == symbol_use.name)
+        {
+            spans.push((position, symbol_use.span.clone()));
         }
     }
 
-    // Add intermediate symbols
-    for (name, _col) in &pil.intermediate_columns {
-        let (spans, messages) = tracker.find_symbol_positions(name);
-        log_messages.extend(messages);
+    let ids: Vec<_> = declared
+        .iter()
+        .map(|declared| index.add_symbol(declared.at(declared.span.clone())))
+        .collect();
+    for (position, span) in spans {
+        index.add_occurrence(declared[position].at(span), ids[position]);
+    }
+}
 
-        for span in spans {
-            index.add_symbol(Symbol {
-                kind: SymbolKind::Intermediate,
-                name: name.clone(),
+/// The span of `name` inside the source of the statement declaring or using it.
+fn name_in(source: &SourceRef, name: &str, source_text: &str) -> Option<Span> {
+    if !is_local(source, source_text) {
+        return None;
+    }
+    let pos = find_word(&source_text[source.start..source.end], name)?;
+    Some(source.start + pos..source.start + pos + name.len())
+}
+
+/// Finds the names of declarations the AST keeps no source reference for,
+/// e.g. `machine Name` or `mod name`, by their keyword. Items are looked up in
+/// source order, each search continuing after the previous item.
+pub(crate) struct ItemLocator<'a> {
+    text: &'a str,
+    tokens: Vec<Span>,
+    next: usize,
+}
+
+impl<'a> ItemLocator<'a> {
+    pub(crate) fn new(text: &'a str) -> Self {
+        Self {
+            text,
+            tokens: code_tokens(text),
+            next: 0,
+        }
+    }
+
+    /// The span of `name` in the next `keyword name`.
+    pub(crate) fn declaration(&mut self, keyword: &str, name: &str) -> Option<Span> {
+        let tokens = &self.tokens[self.next..];
+        let pos = tokens.windows(2).position(|pair| {
+            self.text[pair[0].clone()] == *keyword && self.text[pair[1].clone()] == *name
+        })?;
+        let span = tokens[pos + 1].clone();
+        self.next += pos + 2;
+        Some(span)
+    }
+
+    /// The span of the name the next `use` statement introduces, i.e. its alias
+    /// or the last segment of its path.
+    fn import(&mut self, name: &str) -> Option<Span> {
+        let tokens = &self.tokens[self.next..];
+        let start = tokens
+            .iter()
+            .position(|token| &self.text[token.clone()] == "use")?;
+        let end = start
+            + tokens[start..]
+                .iter()
+                .position(|token| &self.text[token.clone()] == ";")?;
+        let span = tokens[end - 1].clone();
+        self.next += end + 1;
+        (self.text[span.clone()] == *name).then_some(span)
+    }
+
+    /// The span of the last segment of `path`, e.g. `Binary` in
+    /// `std::binary::Binary`, where the path is written inside `source`.
+    fn path_in(&self, source: &SourceRef, path: &str) -> Option<Span> {
+        if !is_local(source, self.text) {
+            return None;
+        }
+        let segments: Vec<_> = path.trim_start_matches("::").split("::").collect();
+        // `a::b` is the tokens `a`, `:`, `:` and `b`.
+        let length = 3 * segments.len() - 2;
+        let first = self
+            .tokens
+            .partition_point(|token| token.start < source.start);
+        let last = self.tokens.partition_point(|token| token.end <= source.end);
+        let tokens = self.tokens.get(first..last)?;
+        tokens
+            .windows(length)
+            .find(|window| {
+                window.iter().enumerate().all(|(i, token)| {
+                    let expected = if i % 3 == 0 { segments[i / 3] } else { ":" };
+                    self.text[token.clone()] == *expected
+                })
+            })
+            .map(|window| window[length - 1].clone())
+    }
+
+    /// Continues the search after `offset`, e.g. past the body of an item.
+    pub(crate) fn skip_to(&mut self, offset: usize) {
+        self.next = self
+            .next
+            .max(self.tokens.partition_point(|token| token.start < offset));
+    }
+}
+
+/// Labels are local to their function, they are indexed where they are
+/// declared and where the function's statements refer to them.
+fn index_labels(
+    function: &str,
+    func: &FunctionSymbol,
+    first_use: usize,
+    index: &mut SemanticIndex,
+    source_text: &str,
+) {
+    let declared = func
+        .body
+        .statements
+        .iter()
+        .filter_map(|statement| {
+            let FunctionStatement::Label(label) = statement else {
+                return None;
+            };
+            Some(Declared {
+                name: label.name.clone(),
+                span: name_in(&label.source, &label.name, source_text)?,
+                kind: SymbolKind::Label,
+                details: SymbolDetails::Label {
+                    function: function.to_string(),
+                },
+            })
+        })
+        .collect();
+    add_symbols(&declared, first_use, index);
+}
+
+fn summarize_machine(machine: &Machine) -> MachineSummary {
+    let mut links = Vec::new();
+    let outgoing = machine
+        .instructions
+        .iter()
+        .flat_map(|instr| &instr.instruction.links)
+        .chain(&machine.links);
+    for link in outgoing {
+        let target = format!("{}.{}", link.to.instance, link.to.callable);
+        if !links.contains(&target) {
+            links.push(target);
+        }
+    }
+
+    MachineSummary {
+        params: machine.params.to_string(),
+        latch: machine.latch.clone(),
+        operation_id: machine.operation_id.clone(),
+        call_selectors: machine.call_selectors.clone(),
+        registers: machine.registers.len(),
+        instructions: machine.instructions.len(),
+        operations: machine
+            .callable
+            .iter()
+            .filter(|callable| matches!(callable.symbol, CallableSymbol::Operation(_)))
+            .count(),
+        submachines: machine
+            .submachines
+            .iter()
+            .map(|submachine| (submachine.name.clone(), submachine.ty.to_string()))
+            .collect(),
+        links,
+    }
+}
+
+fn declared_columns(statement: &PilStatement) -> Option<(SymbolKind, Vec<String>)> {
+    let names = |names: &[PolynomialName]| names.iter().map(|n| n.name.clone()).collect();
+    match statement {
+        PilStatement::PolynomialCommitDeclaration(_, _, polys, _) => {
+            Some((SymbolKind::WitnessColumn, names(polys)))
+        }
+        PilStatement::PolynomialConstantDeclaration(_, polys) => {
+            Some((SymbolKind::FixedColumn, names(polys)))
+        }
+        PilStatement::PolynomialConstantDefinition(_, name, _) => {
+            Some((SymbolKind::FixedColumn, vec![name.clone()]))
+        }
+        // `let w: col;` declares a witness column, with a value it is fixed.
+        PilStatement::LetStatement(_, name, Some(ty), value) if ty.to_string() == "col" => {
+            let kind = if value.is_some() {
+                SymbolKind::FixedColumn
+            } else {
+                SymbolKind::WitnessColumn
+            };
+            Some((kind, vec![name.clone()]))
+        }
+        _ => None,
+    }
+}
+
+fn join<P: std::fmt::Display>(items: &[P]) -> String {
+    items
+        .iter()
+        .map(|item| item.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+fn analyze_pil<T>(
+    pil: &Analyzed<T>,
+    parsed: Option<&PILFile>,
+    index: &mut SemanticIndex,
+    source_text: &str,
+) {
+    let mut declared = Vec::new();
+    let mut declare = |name: &str, source: &SourceRef, kind, details| {
+        // Names are absolute, the declaration spells the local name.
+        if let Some(span) = name_in(source, local_name(name), source_text) {
+            declared.push(Declared {
+                name: name.to_string(),
                 span,
-                details: SymbolDetails::Intermediate,
+                kind,
+                details,
             });
         }
+    };
+
+    for (name, (symbol, value)) in &pil.definitions {
+        let (kind, details) = describe_definition(name, symbol, value.as_ref());
+        declare(name, &symbol.source, kind, details);
+    }
+
+    for (name, (symbol, _)) in &pil.intermediate_columns {
+        let details = declaration(
+            format!("col {}{}", local_name(name), array_suffix(symbol.length)),
+            Some("inter".to_string()),
+            symbol,
+        );
+        declare(name, &symbol.source, SymbolKind::Intermediate, details);
+    }
+
+    for (name, public) in &pil.public_declarations {
+        declare(
+            name,
+            &public.source,
+            SymbolKind::Public,
+            SymbolDetails::Public,
+        );
     }
 
-    // Add trait implementation symbols
     for timpl in &pil.trait_impls {
-        let (spans, messages) = tracker.find_symbol_positions(&timpl.name.to_string());
-        log_messages.extend(messages);
+        declare(
+            &timpl.name.to_string(),
+            &timpl.source_ref,
+            SymbolKind::TraitImpl,
+            SymbolDetails::TraitImpl,
+        );
+    }
 
-        for span in spans {
+    for declared in &declared {
+        index.add_use(SymbolUse {
+            name: declared.name.clone(),
+            span: declared.span.clone(),
+            kind: UseKind::Declaration,
+            scope: String::new(),
+        });
+    }
+
+    if let Some(PILFile(statements)) = parsed {
+        index_pil_statements(statements, &declared, index, source_text);
+    }
+    add_symbols(&declared, 0, index);
+}
+
+/// Adds the namespaces and the references to declared symbols. References are
+/// resolved like the analyzer does, relative to the enclosing namespace first,
+/// so equal local names in different namespaces stay apart.
+fn index_pil_statements(
+    statements: &[PilStatement],
+    declared: &[Declared],
+    index: &mut SemanticIndex,
+    source_text: &str,
+) {
+    let names: HashSet<&str> = declared.iter().map(|d| d.name.as_str()).collect();
+    let mut namespace = String::new();
+
+    for statement in statements {
+        if let PilStatement::Namespace(source, path, ..) = statement {
+            namespace = path.to_string();
+            let Some(span) = name_in(source, local_name(&namespace), source_text) else {
+                continue;
+            };
             index.add_symbol(Symbol {
-                kind: SymbolKind::TraitImpl,
-                name: timpl.name.to_string(),
+                kind: SymbolKind::Namespace,
+                name: namespace.clone(),
                 span,
-                details: SymbolDetails::TraitImpl,
+                details: SymbolDetails::Declaration {
+                    declaration: format!("namespace {namespace}"),
+                    type_info: None,
+                    length: None,
+                    stage: None,
+                    value: None,
+                },
+            });
+            continue;
+        }
+
+        let expressions: Vec<_> = statement.all_children().collect();
+        let written = written_references(&expressions);
+        for expression in expressions {
+            let Expression::Reference(source, reference) = expression else {
+                continue;
+            };
+            if !is_local(source, source_text) {
+                continue;
+            }
+            let path = reference.path.to_string();
+            let relative = if namespace.is_empty() {
+                path.clone()
+            } else {
+                format!("{namespace}::{path}")
+            };
+            let Some(name) = [relative, path]
+                .into_iter()
+                .find(|name| names.contains(name.as_str()))
+            else {
+                continue;
+            };
+            index.add_use(SymbolUse {
+                name,
+                span: source.start..source.end,
+                kind: use_kind(source, &written),
+                scope: String::new(),
             });
         }
     }
+}
+
+fn describe_definition(
+    name: &str,
+    symbol: &PilSymbol,
+    value: Option<&FunctionValueDefinition>,
+) -> (SymbolKind, SymbolDetails) {
+    let short = local_name(name);
+    let array = array_suffix(symbol.length);
+
+    match &symbol.kind {
+        PilSymbolKind::Poly(PolynomialType::Committed) => {
+            let stage = match symbol.stage {
+                Some(stage) if stage > 0 => format!(": stage({stage})"),
+                _ => String::new(),
+            };
+            (
+                SymbolKind::WitnessColumn,
+                declaration(
+                    format!("col witness {short}{array}{stage}"),
+                    Some(format!("col{array}")),
+                    symbol,
+                ),
+            )
+        }
+        PilSymbolKind::Poly(PolynomialType::Constant) => (
+            SymbolKind::FixedColumn,
+            declaration(
+                format!("col fixed {short}{array}"),
+                Some(format!("col{array}")),
+                symbol,
+            ),
+        ),
+        // Intermediate columns are kept in `intermediate_columns`, not here.
+        _ => match value {
+            Some(FunctionValueDefinition::Expression(expression)) => {
+                let kind = match &expression.type_scheme {
+                    Some(TypeScheme {
+                        ty: Type::Function(_),
+                        ..
+                    }) => SymbolKind::Function,
+                    _ => SymbolKind::Constant,
+                };
+                let type_info = expression.type_scheme.as_ref().map(|ty| ty.to_string());
+                let text = match &type_info {
+                    Some(ty) => format!("let {short}: {ty}"),
+                    None => format!("let {short}"),
+                };
+                let mut details = declaration(text, type_info, symbol);
+                if let (SymbolKind::Constant, SymbolDetails::Declaration { value, .. }) =
+                    (&kind, &mut details)
+                {
+                    *value = Some(expression.e.to_string());
+                }
+                (kind, details)
+            }
+            Some(FunctionValueDefinition::TypeDeclaration(TypeDeclaration::Enum(_))) => (
+                SymbolKind::Enum,
+                declaration(format!("enum {short}"), None, symbol),
+            ),
+            Some(FunctionValueDefinition::TypeDeclaration(TypeDeclaration::Struct(_))) => (
+                SymbolKind::Struct,
+                declaration(format!("struct {short}"), None, symbol),
+            ),
+            Some(FunctionValueDefinition::TypeConstructor(enum_decl, variant)) => (
+                SymbolKind::EnumVariant,
+                declaration(format!("{}::{}", enum_decl.name, variant), None, symbol),
+            ),
+            Some(FunctionValueDefinition::TraitDeclaration(_)) => (
+                SymbolKind::Trait,
+                declaration(format!("trait {short}"), None, symbol),
+            ),
+            _ => (SymbolKind::Definition, SymbolDetails::Definition),
+        },
+    }
+}
+
+fn declaration(text: String, type_info: Option<String>, symbol: &PilSymbol) -> SymbolDetails {
+    SymbolDetails::Declaration {
+        declaration: text,
+        type_info,
+        length: symbol.length,
+        stage: symbol.stage,
+        value: None,
+    }
+}
+
+fn array_suffix(length: Option<u64>) -> String {
+    length
+        .map(|length| format!("[{length}]"))
+        .unwrap_or_default()
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit_once("::").map_or(name, |(_, local)| local)
+}
+
+/// Records where registers and other symbols are read or written inside a function body.
+fn collect_function_uses(func: &FunctionSymbol, index: &mut SemanticIndex, source_text: &str) {
+    if !is_local(&func.source, source_text) {
+        return;
+    }
+
+    for statement in func.body.statements.iter() {
+        match statement {
+            FunctionStatement::Assignment(assignment) => {
+                // The assigned registers are named, in order, before the right hand side.
+                let rhs_start = assignment.rhs.source_reference().start;
+                let lhs_end = rhs_start.clamp(assignment.source.start, assignment.source.end);
+                let mut offset = assignment.source.start;
+                for (name, _) in &assignment.lhs_with_reg {
+                    if let Some(pos) = find_word(&source_text[offset..lhs_end], name) {
+                        let start = offset + pos;
+                        add_use(index, name, start, UseKind::Write);
+                        offset = start + name.len();
+                    }
+                }
+
+                collect_reads(&assignment.rhs, index);
+            }
+            FunctionStatement::Instruction(instruction) => {
+                // The statement starts with the name of the invoked instruction.
+                add_use(
+                    index,
+                    &instruction.instruction,
+                    instruction.source.start,
+                    UseKind::Read,
+                );
+                for input in &instruction.inputs {
+                    collect_reads(input, index);
+                }
+            }
+            FunctionStatement::Return(ret) => {
+                for value in &ret.values {
+                    collect_reads(value, index);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Records the declarations of instructions and registers, and where
+/// instructions, links and constraints read registers, columns and instances.
+fn collect_machine_uses(machine: &Machine, index: &mut SemanticIndex, source_text: &str) {
+    let registers: Vec<&str> = machine
+        .registers
+        .iter()
+        .map(|register| register.name.as_str())
+        .collect();
 
-    log_messages
+    for instr in &machine.instructions {
+        if !is_local(&instr.source, source_text) {
+            continue;
+        }
+        let span = instr.source.start..instr.source.end;
+        if let Some(pos) = find_word(&source_text[span.clone()], &instr.name) {
+            let start = span.start + pos;
+            add_use(index, &instr.name, start, UseKind::Declaration);
+        }
+        // The body writes registers with `A' = ..`, recorded before the
+        // parameters and the body are scanned for the registers it reads.
+        for statement in &instr.instruction.body.0 {
+            collect_references(statement.all_children(), index);
+        }
+        collect_word_uses(&registers, span, index, source_text);
+    }
+
+    // Assignment registers and the pc are used implicitly.
+    for register in &machine.registers {
+        if !matches!(register.ty, RegisterTy::Write | RegisterTy::ReadOnly)
+            || !is_local(&register.source, source_text)
+        {
+            continue;
+        }
+        let text = &source_text[register.source.start..register.source.end];
+        if let Some(pos) = find_word(text, &register.name) {
+            let start = register.source.start + pos;
+            add_use(index, &register.name, start, UseKind::Declaration);
+        }
+    }
+
+    let links = machine
+        .instructions
+        .iter()
+        .flat_map(|instr| &instr.instruction.links)
+        .chain(&machine.links);
+    for link in links {
+        if !is_local(&link.source, source_text) {
+            continue;
+        }
+        let span = link.source.start..link.source.end;
+        let target = format!("{}.{}", link.to.instance, link.to.callable);
+        if let Some(pos) = find_word(&source_text[span.clone()], &target) {
+            add_use(index, &link.to.instance, span.start + pos, UseKind::Read);
+        }
+        collect_word_uses(&registers, span, index, source_text);
+    }
+
+    for statement in &machine.pil {
+        if declared_columns(statement).is_some()
+            || !is_local(statement.source_reference(), source_text)
+        {
+            continue;
+        }
+        collect_references(statement.all_children(), index);
+    }
+}
+
+/// Records a read of each of `names` occurring inside `span`.
+fn collect_word_uses(names: &[&str], span: Span, index: &mut SemanticIndex, source_text: &str) {
+    let text = &source_text[span.clone()];
+    for name in names {
+        let mut offset = 0;
+        while let Some(pos) = find_word(&text[offset..], name) {
+            add_use(index, name, span.start + offset + pos, UseKind::Read);
+            offset += pos + name.len();
+        }
+    }
+}
+
+fn add_use(index: &mut SemanticIndex, name: &str, start: usize, kind: UseKind) {
+    index.add_use(SymbolUse {
+        name: name.to_string(),
+        span: start..start + name.len(),
+        kind,
+        scope: String::new(),
+    });
+}
+
+fn collect_reads(expr: &Expression, index: &mut SemanticIndex) {
+    collect_references(expr.all_children(), index);
+}
+
+/// Records a use at each reference among `expressions`: a write where an
+/// identity `x' = ..` sets the next row of `x`, a read everywhere else.
+fn collect_references<'a>(
+    expressions: impl Iterator<Item = &'a Expression>,
+    index: &mut SemanticIndex,
+) {
+    let expressions: Vec<_> = expressions.collect();
+    let written = written_references(&expressions);
+    for expression in expressions {
+        if let Expression::Reference(source, reference) = expression {
+            index.add_use(SymbolUse {
+                name: reference.to_string(),
+                span: source.start..source.end,
+                kind: use_kind(source, &written),
+                scope: String::new(),
+            });
+        }
+    }
+}
+
+/// Start offsets of the references whose next row an identity among
+/// `expressions` sets, e.g. the `x` of `x' = x + 1`.
+fn written_references(expressions: &[&Expression]) -> HashSet<usize> {
+    expressions
+        .iter()
+        .filter_map(|expression| match expression {
+            Expression::BinaryOperation(
+                _,
+                BinaryOperation {
+                    left,
+                    op: BinaryOperator::Identity,
+                    ..
+                },
+            ) => match left.as_ref() {
+                Expression::UnaryOperation(
+                    _,
+                    UnaryOperation {
+                        op: UnaryOperator::Next,
+                        expr,
+                    },
+                ) => match expr.as_ref() {
+                    Expression::Reference(source, _) => Some(source.start),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+fn use_kind(source: &SourceRef, written: &HashSet<usize>) -> UseKind {
+    if written.contains(&source.start) {
+        UseKind::Write
+    } else {
+        UseKind::Read
+    }
+}
+
+/// Source references of imported modules point into other files.
+pub(crate) fn is_local(source: &SourceRef, source_text: &str) -> bool {
+    source.end <= source_text.len()
+        && source
+            .file_contents
+            .as_deref()
+            .is_none_or(|contents| contents == source_text)
+}
+
+pub(crate) fn find_word(text: &str, word: &str) -> Option<usize> {
+    text.match_indices(word).map(|(pos, _)| pos).find(|&pos| {
+        let end = pos + word.len();
+        (pos == 0 || !is_identifier_char(text.as_bytes()[pos - 1] as char))
+            && (end >= text.len() || !is_identifier_char(text.as_bytes()[end] as char))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use powdr_number::GoldilocksField;
+    use tower_lsp::lsp_types::Url;
+
+    fn index(uri: &str, text: &str) -> SemanticIndex {
+        let result = parse::<GoldilocksField>(text, &Url::parse(uri).unwrap());
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        build_semantic_index(&result.analyzed, result.parsed.as_ref(), text)
+    }
+
+    /// Where the symbol at `offset` is declared.
+    fn declared_at(index: &SemanticIndex, offset: usize) -> usize {
+        let id = index.find_symbol_id_at_position(offset).unwrap();
+        index.symbols[&index.declaration_of(id)].span.start
+    }
+
+    #[test]
+    fn qualified_submachine_types_link_to_their_machine() {
+        let text = "mod utils {
+    machine Binary with degree: 8 {
+        col witness x;
+    }
+}
+
+machine Main with degree: 8 {
+    utils::Binary binary;
+}
+";
+        let index = index("file:///test.asm", text);
+
+        let ty = text.find("Binary binary").unwrap();
+        let symbol = index.find_symbol_at_position(ty).unwrap();
+        assert_eq!(symbol.kind, SymbolKind::Machine);
+        assert_eq!(declared_at(&index, ty), text.find("Binary with").unwrap());
+
+        let instance = index
+            .find_symbol_at_position(text.find("binary;").unwrap())
+            .unwrap();
+        assert_eq!(instance.kind, SymbolKind::Submachine);
+        assert_eq!(instance.name, "binary");
+    }
+
+    #[test]
+    fn docs_attach_to_the_declaration() {
+        let text = "machine Main with degree: 8 {
+    reg pc[@pc];
+    reg X[<=];
+    /// The accumulator.
+    reg A;
+
+    function main {
+        A <=X= 1;
+        return;
+    }
+}
+";
+        let index = index("file:///test.asm", text);
+
+        assert!(index.docs.keys().all(|id| index.declaration_of(*id) == *id));
+        let declaration = index
+            .find_symbol_id_at_position(text.find("A;").unwrap())
+            .unwrap();
+        assert_eq!(index.doc(declaration), Some("The accumulator."));
+        let use_ = index
+            .find_symbol_id_at_position(text.find("A <=X=").unwrap())
+            .unwrap();
+        assert_ne!(use_, declaration);
+        assert_eq!(index.doc(use_), Some("The accumulator."));
+    }
+
+    #[test]
+    fn uses_link_to_the_declaration_of_their_machine() {
+        let text = "machine Main with degree: 8 {
+    reg pc[@pc];
+    reg X[<=];
+    reg A;
+
+    function main {
+        A <=X= 1;
+        return;
+    }
+}
+
+machine Other with degree: 8 {
+    reg pc[@pc];
+    reg X[<=];
+    reg A;
+
+    function main {
+        A <=X= 2;
+        return;
+    }
+}
+";
+        let index = index("file:///test.asm", text);
+
+        let first = text.find("A <=X= 1").unwrap();
+        let second = text.find("A <=X= 2").unwrap();
+        assert_eq!(declared_at(&index, first), text.find("A;").unwrap());
+        assert_eq!(declared_at(&index, second), text.rfind("A;").unwrap());
+    }
+
+    #[test]
+    fn pil_uses_link_to_the_column_of_their_namespace() {
+        let text = "namespace Main(8);
+col witness x;
+x' = x + 1;
+namespace Other(8);
+col witness x;
+x = 0;
+";
+        let index = index("file:///test.pil", text);
+
+        let declarations: Vec<_> = text.match_indices("x;").map(|(pos, _)| pos).collect();
+        assert_eq!(
+            declared_at(&index, text.find("x'").unwrap()),
+            declarations[0]
+        );
+        assert_eq!(
+            declared_at(&index, text.find("x = 0").unwrap()),
+            declarations[1]
+        );
+    }
 }
@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use tracing::warn;
+
+/// Prefix of diagnostics reporting an analysis that ran out of time.
+pub const TIMED_OUT: &str = "analysis timed out";
+
+pub const DEFAULT_TIME_BUDGET: Duration = Duration::from_secs(5);
+
+/// Threads of one document still running an analysis that ran out of time.
+/// They cannot be stopped, so past this limit new analyses of that document
+/// are refused until one finishes. Other documents are not affected.
+const MAX_RUNAWAY: usize = 2;
+
+/// Stack of the analysis threads, powdr evaluates recursively.
+const STACK_SIZE: usize = 64 * 1024 * 1024;
+
+static TIME_BUDGET_MS: AtomicU64 = AtomicU64::new(DEFAULT_TIME_BUDGET.as_millis() as u64);
+static RUNAWAY: Mutex<BTreeMap<String, usize>> = Mutex::new(BTreeMap::new());
+
+const RUNNING: u8 = 0;
+const DONE: u8 = 1;
+const ABANDONED: u8 = 2;
+
+pub fn set_time_budget(budget: Duration) {
+    TIME_BUDGET_MS.store(budget.as_millis() as u64, Ordering::Relaxed);
+}
+
+pub fn time_budget() -> Duration {
+    Duration::from_millis(TIME_BUDGET_MS.load(Ordering::Relaxed))
+}
+
+fn runaway() -> std::sync::MutexGuard<'static, BTreeMap<String, usize>> {
+    RUNAWAY.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn finish_runaway(key: &str) {
+    let mut runaway = runaway();
+    if let Some(count) = runaway.get_mut(key) {
+        *count -= 1;
+        if *count == 0 {
+            runaway.remove(key);
+        }
+    }
+}
+
+/// Runs `f` for the document `key` on a worker thread and waits at most the
+/// time budget for it. On timeout the worker is left to finish on its own and
+/// its result is dropped.
+pub fn with_time_budget<R, F>(key: &str, f: F) -> Result<R, String>
+where
+    R: Send + 'static,
+    F: FnOnce() -> R + Send + 'static,
+{
+    if runaway()
+        .get(key)
+        .is_some_and(|count| *count >= MAX_RUNAWAY)
+    {
+        return Err(format!(
+            "{TIMED_OUT}: earlier analyses of this document are still running"
+        ));
+    }
+
+    let budget = time_budget();
+    let state = Arc::new(AtomicU8::new(RUNNING));
+    let (sender, receiver) = mpsc::channel();
+
+    thread::Builder::new()
+        .name("powdr-analysis".to_string())
+        .stack_size(STACK_SIZE)
+        .spawn({
+            let state = state.clone();
+            let key = key.to_string();
+            move || {
+                let _ = sender.send(f());
+                if state.swap(DONE, Ordering::AcqRel) == ABANDONED {
+                    finish_runaway(&key);
+                }
+            }
+        })
+        .map_err(|e| e.to_string())?;
+
+    match receiver.recv_timeout(budget) {
+        Ok(result) => Ok(result),
+        Err(RecvTimeoutError::Timeout) => {
+            *runaway().entry(key.to_string()).or_default() += 1;
+            if state.swap(ABANDONED, Ordering::AcqRel) == DONE {
+                // Finished right at the deadline.
+                finish_runaway(key);
+                return receiver.recv().map_err(|e| e.to_string());
+            }
+            warn!(?budget, key, "analysis ran out of time");
+            Err(format!("{TIMED_OUT} after {budget:?}"))
+        }
+        Err(RecvTimeoutError::Disconnected) => Err("analysis worker stopped".to_string()),
+    }
+}
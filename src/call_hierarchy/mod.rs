@@ -0,0 +1,513 @@
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+
+use crate::analyzer::{find_word, is_identifier_char};
+use crate::parser::{convert_position, position_to_offset, source_location};
+use crate::span::Span;
+use powdr_ast::asm_analysis::{
+    AnalysisASMFile, CallableSymbol, FunctionStatement, LinkDefinition, Machine,
+};
+use powdr_ast::parsed::Expression;
+use powdr_ast::parsed::visitor::AllChildren;
+use powdr_parser_util::SourceRef;
+use serde_json::json;
+use tower_lsp::lsp_types::*;
+
+/// A callable is identified by the absolute path of its machine and its name.
+type CallableKey = (String, String);
+
+#[derive(Debug)]
+struct CallableNode {
+    key: CallableKey,
+    kind: SymbolKind,
+    source: SourceRef,
+}
+
+#[derive(Debug)]
+struct CallEdge {
+    caller: CallableKey,
+    callee: CallableKey,
+    site: SourceRef,
+    len: usize,
+}
+
+/// The callables of one asm document and the calls between them. It only
+/// depends on the document's analysis, so it is built once per analysis.
+#[derive(Debug)]
+pub struct CallGraph {
+    pub uri: Url,
+    text: Arc<str>,
+    nodes: Vec<CallableNode>,
+    edges: Vec<CallEdge>,
+}
+
+pub struct CallHierarchyProvider<'a> {
+    graphs: &'a [Arc<CallGraph>],
+}
+
+impl<'a> CallHierarchyProvider<'a> {
+    pub fn new(graphs: &'a [Arc<CallGraph>]) -> Self {
+        Self { graphs }
+    }
+
+    pub fn prepare(&self, uri: &Url, position: Position) -> Option<Vec<CallHierarchyItem>> {
+        let graph = self.graphs.iter().find(|graph| graph.uri == *uri)?;
+        let offset = position_to_offset(position, &graph.text)?;
+        let name = word_at(&graph.text, offset)?;
+
+        // Prefer the callable declared by the machine around the cursor, so that
+        // `mload` in `Main` does not resolve to the operation of the memory machine.
+        let candidates: Vec<_> = graph
+            .nodes
+            .iter()
+            .filter(|node| node.key.1 == name && graph.is_local(&node.source))
+            .collect();
+        let node = candidates
+            .iter()
+            .find(|node| graph.machine_span(&node.key.0).contains(&offset))
+            .or(candidates.first())?;
+
+        Some(vec![graph.item(node)?])
+    }
+
+    pub fn incoming_calls(&self, item: &CallHierarchyItem) -> Vec<CallHierarchyIncomingCall> {
+        let Some(key) = item_key(item) else {
+            return vec![];
+        };
+
+        let mut calls: BTreeMap<CallableKey, CallHierarchyIncomingCall> = BTreeMap::new();
+        for graph in self.graphs.iter() {
+            for edge in graph.edges.iter().filter(|edge| edge.callee == key) {
+                let Some((_, range)) = graph.locate(&edge.site, edge.len) else {
+                    continue;
+                };
+                if !calls.contains_key(&edge.caller) {
+                    let Some(from) = graph.node(&edge.caller).and_then(|node| graph.item(node))
+                    else {
+                        continue;
+                    };
+                    calls.insert(
+                        edge.caller.clone(),
+                        CallHierarchyIncomingCall {
+                            from,
+                            from_ranges: vec![],
+                        },
+                    );
+                }
+
+                let call = calls.get_mut(&edge.caller).unwrap();
+                if !call.from_ranges.contains(&range) {
+                    call.from_ranges.push(range);
+                }
+            }
+        }
+
+        calls.into_values().collect()
+    }
+
+    pub fn outgoing_calls(&self, item: &CallHierarchyItem) -> Vec<CallHierarchyOutgoingCall> {
+        let Some(key) = item_key(item) else {
+            return vec![];
+        };
+        let Some(graph) = self.graphs.iter().find(|graph| graph.uri == item.uri) else {
+            return vec![];
+        };
+
+        let mut calls: BTreeMap<CallableKey, CallHierarchyOutgoingCall> = BTreeMap::new();
+        for edge in graph.edges.iter().filter(|edge| edge.caller == key) {
+            let Some((_, range)) = graph.locate(&edge.site, edge.len) else {
+                continue;
+            };
+            if !calls.contains_key(&edge.callee) {
+                let Some(to) = graph.node(&edge.callee).and_then(|node| graph.item(node)) else {
+                    continue;
+                };
+                calls.insert(
+                    edge.callee.clone(),
+                    CallHierarchyOutgoingCall {
+                        to,
+                        from_ranges: vec![],
+                    },
+                );
+            }
+
+            calls.get_mut(&edge.callee).unwrap().from_ranges.push(range);
+        }
+
+        calls.into_values().collect()
+    }
+}
+
+impl CallGraph {
+    fn node(&self, key: &CallableKey) -> Option<&CallableNode> {
+        self.nodes.iter().find(|node| node.key == *key)
+    }
+
+    fn item(&self, node: &CallableNode) -> Option<CallHierarchyItem> {
+        let (uri, range) = self.locate(&node.source, node.source.end - node.source.start)?;
+        let text = self.text_of(&node.source);
+        let name = &node.key.1;
+        let selection_range = find_word(&text[node.source.start..node.source.end], name)
+            .map(|pos| {
+                let start = node.source.start + pos;
+                Range::new(
+                    convert_position(start, text),
+                    convert_position(start + name.len(), text),
+                )
+            })
+            .unwrap_or(range);
+
+        Some(CallHierarchyItem {
+            name: name.clone(),
+            kind: node.kind,
+            tags: None,
+            detail: Some(node.key.0.clone()),
+            uri,
+            range,
+            selection_range,
+            data: Some(json!({ "machine": node.key.0, "name": name })),
+        })
+    }
+
+    fn locate(&self, source: &SourceRef, len: usize) -> Option<(Url, Range)> {
+        source_location(
+            source,
+            source.start..source.start + len,
+            &self.uri,
+            &self.text,
+        )
+        .map(|location| (location.uri, location.range))
+    }
+
+    fn text_of<'b>(&'b self, source: &'b SourceRef) -> &'b str {
+        source.file_contents.as_deref().unwrap_or(&self.text)
+    }
+
+    fn is_local(&self, source: &SourceRef) -> bool {
+        self.text_of(source) == &*self.text
+    }
+
+    fn machine_span(&self, machine: &str) -> Span {
+        self.nodes
+            .iter()
+            .filter(|node| node.key.0 == machine && self.is_local(&node.source))
+            .fold(usize::MAX..0, |span, node| {
+                span.start.min(node.source.start)..span.end.max(node.source.end)
+            })
+    }
+}
+
+/// Builds the call graph of an asm document.
+pub fn build_call_graph(uri: &Url, text: &Arc<str>, asm: &AnalysisASMFile) -> CallGraph {
+    let mut nodes = Vec::new();
+    let mut edges = Vec::new();
+
+    for (path, machine) in asm.machines() {
+        let machine_name = path.to_string();
+        let key = |name: &str| (machine_name.clone(), name.to_string());
+        let instructions: HashSet<&str> = machine
+            .instructions
+            .iter()
+            .map(|instr| instr.name.as_str())
+            .collect();
+        let link_edge = |caller: CallableKey, link: &LinkDefinition| {
+            resolve_link(machine, link).map(|callee| CallEdge {
+                caller,
+                callee,
+                site: link.source.clone(),
+                len: link.source.end - link.source.start,
+            })
+        };
+
+        for instr in &machine.instructions {
+            nodes.push(CallableNode {
+                key: key(&instr.name),
+                kind: SymbolKind::METHOD,
+                source: instr.source.clone(),
+            });
+
+            // Instruction links fire whenever the instruction is executed.
+            edges.extend(
+                instr
+                    .instruction
+                    .links
+                    .iter()
+                    .filter_map(|link| link_edge(key(&instr.name), link)),
+            );
+        }
+
+        let mut operations = Vec::new();
+        for callable in &machine.callable {
+            match callable.symbol {
+                CallableSymbol::Function(func) => {
+                    nodes.push(CallableNode {
+                        key: key(callable.name),
+                        kind: SymbolKind::FUNCTION,
+                        source: func.source.clone(),
+                    });
+
+                    for statement in func.body.statements.iter() {
+                        match statement {
+                            FunctionStatement::Instruction(instr)
+                                if instructions.contains(instr.instruction.as_str()) =>
+                            {
+                                edges.push(CallEdge {
+                                    caller: key(callable.name),
+                                    callee: key(&instr.instruction),
+                                    site: instr.source.clone(),
+                                    len: instr.instruction.len(),
+                                });
+                            }
+                            FunctionStatement::Assignment(assignment) => {
+                                for child in assignment.rhs.all_children() {
+                                    if let Expression::Reference(source, reference) = child {
+                                        let name = reference.to_string();
+                                        if instructions.contains(name.as_str()) {
+                                            edges.push(CallEdge {
+                                                caller: key(callable.name),
+                                                callee: key(&name),
+                                                site: source.clone(),
+                                                len: source.end - source.start,
+                                            });
+                                        }
+                                    }
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                CallableSymbol::Operation(op) => {
+                    nodes.push(CallableNode {
+                        key: key(callable.name),
+                        kind: SymbolKind::METHOD,
+                        source: op.source.clone(),
+                    });
+                    operations.push((callable.name, op));
+                }
+            }
+        }
+
+        // A link of the machine itself fires on the rows its flag selects. An
+        // unconditional one fires for every operation, a conditional one for the
+        // operations whose parameters it passes on. Any other is attributed to
+        // the machine, which becomes a node of its own.
+        for link in &machine.links {
+            let link_text = link_text(text, &link.source);
+            let unconditional = link_text.is_some_and(|link_text| {
+                let rest = link_text
+                    .trim_start()
+                    .trim_start_matches("link")
+                    .trim_start();
+                rest.starts_with("=>") || rest.starts_with("~>")
+            });
+            let callers: Vec<&str> = operations
+                .iter()
+                .filter(|(_, op)| {
+                    unconditional
+                        || link_text.is_some_and(|link_text| {
+                            op.params
+                                .inputs
+                                .iter()
+                                .chain(&op.params.outputs)
+                                .any(|param| find_word(link_text, &param.name).is_some())
+                        })
+                })
+                .map(|(name, _)| *name)
+                .collect();
+
+            if callers.is_empty() {
+                let machine_key = key(&path.clone().pop().unwrap_or_default());
+                if !nodes.iter().any(|node| node.key == machine_key) {
+                    nodes.push(CallableNode {
+                        key: machine_key.clone(),
+                        kind: SymbolKind::CLASS,
+                        source: link.source.clone(),
+                    });
+                }
+                edges.extend(link_edge(machine_key, link));
+            } else {
+                edges.extend(
+                    callers
+                        .into_iter()
+                        .filter_map(|caller| link_edge(key(caller), link)),
+                );
+            }
+        }
+    }
+
+    CallGraph {
+        uri: uri.clone(),
+        text: text.clone(),
+        nodes,
+        edges,
+    }
+}
+
+/// The text of a link declared in the document, `None` for imported ones.
+fn link_text<'a>(text: &'a str, source: &SourceRef) -> Option<&'a str> {
+    source
+        .file_contents
+        .as_deref()
+        .is_none_or(|contents| contents == text)
+        .then(|| text.get(source.start..source.end))
+        .flatten()
+}
+
+fn resolve_link(machine: &Machine, link: &LinkDefinition) -> Option<CallableKey> {
+    machine
+        .submachines
+        .iter()
+        .find(|submachine| submachine.name == link.to.instance)
+        .map(|submachine| (submachine.ty.to_string(), link.to.callable.clone()))
+}
+
+fn item_key(item: &CallHierarchyItem) -> Option<CallableKey> {
+    let data = item.data.as_ref()?;
+    Some((
+        data.get("machine")?.as_str()?.to_string(),
+        data.get("name")?.as_str()?.to_string(),
+    ))
+}
+
+fn word_at(text: &str, offset: usize) -> Option<&str> {
+    if offset > text.len() {
+        return None;
+    }
+
+    let is_word = |c: char| is_identifier_char(c) && c != ':';
+    let start = text[..offset]
+        .rfind(|c: char| !is_word(c))
+        .map_or(0, |pos| pos + 1);
+    let end = text[offset..]
+        .find(|c: char| !is_word(c))
+        .map_or(text.len(), |pos| offset + pos);
+
+    (start < end).then(|| &text[start..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{AnalyzedDoc, parse};
+    use powdr_number::GoldilocksField;
+
+    fn graph(text: &str) -> Arc<CallGraph> {
+        let uri = Url::parse("file:///test.asm").unwrap();
+        let result = parse::<GoldilocksField>(text, &uri);
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        let AnalyzedDoc::ASM(asm) = &result.analyzed else {
+            unreachable!()
+        };
+        Arc::new(build_call_graph(&uri, &Arc::from(text), asm))
+    }
+
+    /// The item of the callable whose name is at `needle`.
+    fn item_at(provider: &CallHierarchyProvider, text: &str, needle: &str) -> CallHierarchyItem {
+        let uri = Url::parse("file:///test.asm").unwrap();
+        let position = convert_position(text.find(needle).unwrap(), text);
+        provider.prepare(&uri, position).unwrap().remove(0)
+    }
+
+    fn names(items: impl IntoIterator<Item = CallHierarchyItem>) -> Vec<(String, String)> {
+        items
+            .into_iter()
+            .map(|item| (item.detail.unwrap(), item.name))
+            .collect()
+    }
+
+    const TWO_MACHINES: &str = "machine Main with degree: 8 {
+    Arith arith;
+
+    reg pc[@pc];
+    reg X[<=];
+    reg Y[<=];
+    reg Z[<=];
+    reg A;
+
+    instr add X, Y -> Z link => Z = arith.add(X, Y);
+
+    function main {
+        A <== add(1, 2);
+        return;
+    }
+}
+
+machine Arith with degree: 8, latch: latch, operation_id: operation_id {
+    operation add<0> x, y -> z;
+
+    col witness operation_id;
+    col fixed latch = [1]*;
+    col witness x, y, z;
+    z = x + y;
+}
+";
+
+    #[test]
+    fn calls_cross_into_the_submachine() {
+        let graphs = [graph(TWO_MACHINES)];
+        let provider = CallHierarchyProvider::new(&graphs);
+
+        let instr = item_at(&provider, TWO_MACHINES, "add X, Y");
+        assert_eq!(instr.detail.as_deref(), Some("::Main"));
+        let outgoing = provider.outgoing_calls(&instr);
+        assert_eq!(
+            names(outgoing.into_iter().map(|call| call.to)),
+            vec![("::Arith".to_string(), "add".to_string())]
+        );
+        let incoming = provider.incoming_calls(&instr);
+        assert_eq!(
+            names(incoming.into_iter().map(|call| call.from)),
+            vec![("::Main".to_string(), "main".to_string())]
+        );
+
+        let operation = item_at(&provider, TWO_MACHINES, "add<0>");
+        assert_eq!(operation.detail.as_deref(), Some("::Arith"));
+        let incoming = provider.incoming_calls(&operation);
+        assert_eq!(incoming.len(), 1);
+        assert_eq!(incoming[0].from.name, "add");
+        assert_eq!(incoming[0].from.detail.as_deref(), Some("::Main"));
+        // The call site is the link of the instruction.
+        assert_eq!(incoming[0].from_ranges[0].start.line, 9);
+        assert!(provider.outgoing_calls(&operation).is_empty());
+    }
+
+    #[test]
+    fn recursive_operations_call_themselves() {
+        let text = "machine Counter with degree: 8, latch: latch, operation_id: operation_id {
+    Counter inner;
+
+    operation count<0> x;
+
+    col witness operation_id;
+    col fixed latch = [1]*;
+    col witness x;
+
+    link => inner.count(x);
+}
+";
+        let graphs = [graph(text)];
+        let provider = CallHierarchyProvider::new(&graphs);
+
+        let count = item_at(&provider, text, "count<0>");
+        let own = vec![("::Counter".to_string(), "count".to_string())];
+        assert_eq!(
+            names(
+                provider
+                    .incoming_calls(&count)
+                    .into_iter()
+                    .map(|call| call.from)
+            ),
+            own
+        );
+        assert_eq!(
+            names(
+                provider
+                    .outgoing_calls(&count)
+                    .into_iter()
+                    .map(|call| call.to)
+            ),
+            own
+        );
+    }
+}
@@ -0,0 +1,249 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use powdr_number::FieldElement;
+use serde_json::{Value, json};
+use tower_lsp::lsp_types::*;
+
+use crate::compile::lower;
+use crate::parser::AnalyzedDoc;
+use crate::soundness::lowered_underconstrained_columns;
+use crate::workspace::{find_source_files, is_source_file};
+
+const USAGE: &str = "Usage: powdr-lsp check [--format human|json|sarif] <paths...>";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Human,
+    Json,
+    Sarif,
+}
+
+pub struct FileDiagnostics {
+    pub path: PathBuf,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Entry point of `powdr-lsp check`, returns the process exit code.
+pub fn run<T: FieldElement>(args: &[String]) -> i32 {
+    let mut format = OutputFormat::Human;
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                format = match args.next().map(String::as_str) {
+                    Some("human") => OutputFormat::Human,
+                    Some("json") => OutputFormat::Json,
+                    Some("sarif") => OutputFormat::Sarif,
+                    _ => {
+                        eprintln!("{USAGE}");
+                        return 2;
+                    }
+                }
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    if paths.is_empty() {
+        eprintln!("{USAGE}");
+        return 2;
+    }
+
+    let results = check_paths::<T>(&paths);
+    println!("{}", render(&results, format));
+
+    if has_errors(&results) { 1 } else { 0 }
+}
+
+/// Runs the same pipeline as the editor on every source file in `paths`.
+/// Paths that cannot be read are reported as an error of that path, the
+/// remaining files are still checked. Explicitly named files that are not
+/// `.asm` or `.pil` sources are skipped with a warning.
+pub fn check_paths<T: FieldElement>(paths: &[PathBuf]) -> Vec<FileDiagnostics> {
+    let mut results = Vec::new();
+
+    for path in paths {
+        let files = if path.is_dir() {
+            match find_source_files(path) {
+                Ok(files) => files,
+                Err(e) => {
+                    results.push(unreadable(path, &e));
+                    continue;
+                }
+            }
+        } else if path.exists() && !is_source_file(path) {
+            eprintln!(
+                "warning: skipping {}, it is not a .asm or .pil file",
+                path.display()
+            );
+            continue;
+        } else {
+            vec![path.clone()]
+        };
+
+        for file in files {
+            results.push(check_file::<T>(&file).unwrap_or_else(|e| unreadable(&file, &e)));
+        }
+    }
+
+    results
+}
+
+fn check_file<T: FieldElement>(path: &Path) -> io::Result<FileDiagnostics> {
+    let path = path.canonicalize()?;
+    let content = fs::read_to_string(&path)?;
+    let uri = Url::from_file_path(&path)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid file path"))?;
+
+    let mut result = crate::parser::parse::<T>(&content, &uri);
+    let index =
+        crate::analyzer::build_semantic_index(&result.analyzed, result.parsed.as_ref(), &content);
+    result
+        .diagnostics
+        .extend(crate::lints::lint(&content, &result.analyzed, &index));
+    if let AnalyzedDoc::ASM(asm) = &result.analyzed {
+        let lowered = lower::<T>(&uri, asm.clone());
+        result
+            .diagnostics
+            .extend(lowered_underconstrained_columns(&content, &lowered));
+    }
+
+    Ok(FileDiagnostics {
+        path,
+        diagnostics: result.diagnostics,
+    })
+}
+
+fn unreadable(path: &Path, error: &io::Error) -> FileDiagnostics {
+    FileDiagnostics {
+        path: path.to_path_buf(),
+        diagnostics: vec![Diagnostic {
+            range: Range::default(),
+            severity: Some(DiagnosticSeverity::ERROR),
+            message: format!("Cannot read {}: {}", path.display(), error),
+            source: Some("powdr".to_string()),
+            ..Default::default()
+        }],
+    }
+}
+
+pub fn has_errors(results: &[FileDiagnostics]) -> bool {
+    results.iter().any(|file| {
+        file.diagnostics
+            .iter()
+            .any(|d| d.severity == Some(DiagnosticSeverity::ERROR))
+    })
+}
+
+pub fn render(results: &[FileDiagnostics], format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Human => render_human(results),
+        OutputFormat::Json => serde_json::to_string_pretty(&render_json(results)).unwrap(),
+        OutputFormat::Sarif => serde_json::to_string_pretty(&render_sarif(results)).unwrap(),
+    }
+}
+
+fn render_human(results: &[FileDiagnostics]) -> String {
+    let mut lines = Vec::new();
+    let mut count = 0;
+
+    for file in results {
+        for diagnostic in &file.diagnostics {
+            count += 1;
+            lines.push(format!(
+                "{}:{}:{}: {}: {}",
+                file.path.display(),
+                diagnostic.range.start.line + 1,
+                diagnostic.range.start.character + 1,
+                severity_name(diagnostic.severity),
+                diagnostic.message
+            ));
+        }
+    }
+
+    lines.push(format!(
+        "Checked {} file(s), found {} problem(s)",
+        results.len(),
+        count
+    ));
+    lines.join("\n")
+}
+
+fn render_json(results: &[FileDiagnostics]) -> Value {
+    let diagnostics: Vec<Value> = results
+        .iter()
+        .flat_map(|file| {
+            file.diagnostics.iter().map(|diagnostic| {
+                json!({
+                    "file": file.path,
+                    "severity": severity_name(diagnostic.severity),
+                    "message": diagnostic.message,
+                    "range": diagnostic.range,
+                })
+            })
+        })
+        .collect();
+
+    json!(diagnostics)
+}
+
+fn render_sarif(results: &[FileDiagnostics]) -> Value {
+    let sarif_results: Vec<Value> = results
+        .iter()
+        .flat_map(|file| {
+            let uri = Url::from_file_path(&file.path)
+                .map(|uri| uri.to_string())
+                .unwrap_or_else(|_| file.path.display().to_string());
+
+            file.diagnostics.iter().map(move |diagnostic| {
+                // SARIF lines and columns are 1-based.
+                json!({
+                    "level": match diagnostic.severity {
+                        Some(DiagnosticSeverity::ERROR) | None => "error",
+                        Some(DiagnosticSeverity::WARNING) => "warning",
+                        _ => "note",
+                    },
+                    "message": { "text": diagnostic.message },
+                    "locations": [{
+                        "physicalLocation": {
+                            "artifactLocation": { "uri": uri },
+                            "region": {
+                                "startLine": diagnostic.range.start.line + 1,
+                                "startColumn": diagnostic.range.start.character + 1,
+                                "endLine": diagnostic.range.end.line + 1,
+                                "endColumn": diagnostic.range.end.character + 1,
+                            }
+                        }
+                    }]
+                })
+            })
+        })
+        .collect();
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                }
+            },
+            "results": sarif_results,
+        }]
+    })
+}
+
+fn severity_name(severity: Option<DiagnosticSeverity>) -> &'static str {
+    match severity {
+        Some(DiagnosticSeverity::WARNING) => "warning",
+        Some(DiagnosticSeverity::INFORMATION) => "info",
+        Some(DiagnosticSeverity::HINT) => "hint",
+        _ => "error",
+    }
+}
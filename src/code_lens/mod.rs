@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+
+use crate::analyzer::ItemLocator;
+use crate::compile::MachineStats;
+use crate::parser::convert_position;
+use powdr_ast::parsed::SourceReference;
+use powdr_ast::parsed::asm::{ASMModule, AbsoluteSymbolPath, Module, ModuleStatement, SymbolValue};
+use serde_json::json;
+use tower_lsp::lsp_types::*;
+
+pub const SHOW_PIL_COMMAND: &str = "powdr.showPil";
+
+pub struct CodeLensProvider<'a> {
+    text: &'a str,
+    stats: Option<&'a BTreeMap<String, MachineStats>>,
+}
+
+impl<'a> CodeLensProvider<'a> {
+    pub fn new(text: &'a str, stats: Option<&'a BTreeMap<String, MachineStats>>) -> Self {
+        Self { text, stats }
+    }
+
+    pub fn get_code_lenses(&self, uri: &Url) -> Vec<CodeLens> {
+        let Ok(program) = powdr_parser::parse_asm(None, self.text) else {
+            return vec![];
+        };
+        let mut machines = Vec::new();
+        let mut locator = ItemLocator::new(self.text);
+        self.collect_machines(
+            &program.main,
+            &AbsoluteSymbolPath::default(),
+            &mut locator,
+            &mut machines,
+        );
+
+        let mut lenses = Vec::new();
+        for (path, range) in machines {
+            if let Some(stats) = self.stats.and_then(|stats| stats.get(&path.to_string())) {
+                lenses.push(CodeLens {
+                    range,
+                    command: Some(Command::new(format_stats(stats), String::new(), None)),
+                    data: None,
+                });
+            }
+
+            lenses.push(CodeLens {
+                range,
+                command: Some(Command::new(
+                    "Show generated PIL".to_string(),
+                    SHOW_PIL_COMMAND.to_string(),
+                    Some(vec![json!(uri)]),
+                )),
+                data: None,
+            });
+        }
+
+        lenses
+    }
+
+    /// The machines of the module with their absolute path and the range of
+    /// their name. Machines carry no source reference, their names are found
+    /// by the `machine` keyword in the code, outside comments and strings.
+    /// Machines without statements compile to nothing and get no lenses.
+    fn collect_machines(
+        &self,
+        module: &ASMModule,
+        path: &AbsoluteSymbolPath,
+        locator: &mut ItemLocator,
+        machines: &mut Vec<(AbsoluteSymbolPath, Range)>,
+    ) {
+        for statement in &module.statements {
+            let ModuleStatement::SymbolDefinition(definition) = statement else {
+                continue;
+            };
+            let path = path.with_part(&definition.name);
+
+            match &definition.value {
+                SymbolValue::Machine(machine) => {
+                    let Some(span) = locator.declaration("machine", &definition.name) else {
+                        continue;
+                    };
+                    let Some(end) = machine
+                        .statements
+                        .iter()
+                        .map(|statement| statement.source_reference().end)
+                        .max()
+                    else {
+                        continue;
+                    };
+                    locator.skip_to(end);
+                    let range = Range::new(
+                        convert_position(span.start, self.text),
+                        convert_position(span.end, self.text),
+                    );
+                    machines.push((path, range));
+                }
+                SymbolValue::Module(module) => {
+                    if locator.declaration("mod", &definition.name).is_none() {
+                        continue;
+                    }
+                    if let Module::Local(inner) = module {
+                        self.collect_machines(inner, &path, locator, machines);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn format_stats(stats: &MachineStats) -> String {
+    let degree = match (stats.min_degree, stats.max_degree) {
+        (Some(min), Some(max)) if min == max => format!("degree {}", min),
+        (Some(min), Some(max)) => format!("degree {}..{}", min, max),
+        _ => "degree unknown".to_string(),
+    };
+
+    let instances = if stats.instances > 1 {
+        format!(" ({} instances)", stats.instances)
+    } else {
+        String::new()
+    };
+
+    format!(
+        "{} witness, {} fixed | {} identities, {} lookups, {} permutations | {}{}",
+        stats.witness_columns,
+        stats.fixed_columns,
+        stats.identities,
+        stats.lookups,
+        stats.permutations,
+        degree,
+        instances
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lenses_skip_commented_out_machines() {
+        let text = "// machine Main with degree: 8 { reg pc[@pc]; }
+/* machine Main */
+machine Main with degree: 8 {
+    reg pc[@pc];
+    col witness my_machine;
+}
+";
+        let uri = Url::parse("file:///test.asm").unwrap();
+        let lenses = CodeLensProvider::new(text, None).get_code_lenses(&uri);
+
+        let ranges: Vec<_> = lenses.iter().map(|lens| lens.range).collect();
+        assert_eq!(
+            ranges,
+            vec![Range::new(Position::new(2, 8), Position::new(2, 12))]
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::BTreeMap;
+
+use powdr_ast::analyzed::{AlgebraicExpression, Analyzed, Identity, PolynomialType, SymbolKind};
+use powdr_ast::asm_analysis::{AnalysisASMFile, Machine};
+use powdr_ast::parsed::PILFile;
+use powdr_ast::parsed::asm::AbsoluteSymbolPath;
+use powdr_ast::parsed::visitor::AllChildren;
+use powdr_number::FieldElement;
+use tower_lsp::lsp_types::Url;
+
+use crate::budget::with_time_budget;
+use crate::panic::catch_panic;
+
+pub const ENTRY_MACHINE: &str = "Main";
+
+/// Column and constraint counts a machine contributes to the linked PIL.
+#[derive(Debug, Clone, Default)]
+pub struct MachineStats {
+    pub instances: usize,
+    pub witness_columns: usize,
+    pub fixed_columns: usize,
+    pub identities: usize,
+    pub lookups: usize,
+    pub permutations: usize,
+    pub min_degree: Option<u64>,
+    pub max_degree: Option<u64>,
+}
+
+/// The linked PIL of an asm program, both as generated and after analysis.
+pub struct CompiledPil<T> {
+    pub linked: PILFile,
+    pub analyzed: Analyzed<T>,
+}
+
+/// Runs the asm to PIL lowering (constraint generation, airgen and linker) and
+/// analyzes the linked PIL.
+pub fn compile_to_pil<T: FieldElement>(
+    asm: AnalysisASMFile,
+) -> Result<CompiledPil<T>, Vec<String>> {
+    if !asm.machines().any(|(path, _)| {
+        path.relative_to(&AbsoluteSymbolPath::default()).to_string() == ENTRY_MACHINE
+    }) {
+        return Err(vec![format!("No `{ENTRY_MACHINE}` machine to compile")]);
+    }
+
+    let constrained = powdr_asm_to_pil::compile::<T>(asm);
+    let graph = powdr_airgen::compile(constrained);
+    let linked = powdr_linker::link(graph)?;
+
+    let analyzed = powdr_pil_analyzer::analyze_ast::<T>(linked.clone()).map_err(|errors| {
+        errors
+            .into_iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>()
+    })?;
+
+    Ok(CompiledPil { linked, analyzed })
+}
+
+/// The outcome of lowering an asm document, see `lower`.
+pub type Lowered<T> = Result<CompiledPil<T>, Vec<String>>;
+
+/// `compile_to_pil` within the time budget. Panics and timeouts are reported
+/// as errors like the ones of the lowering itself.
+pub fn lower<T: FieldElement>(uri: &Url, asm: AnalysisASMFile) -> Lowered<T> {
+    match with_time_budget(uri.as_str(), move || {
+        catch_panic(|| compile_to_pil::<T>(asm))
+    }) {
+        Ok(Ok(lowered)) => lowered,
+        Ok(Err(message)) | Err(message) => Err(vec![message]),
+    }
+}
+
+/// Groups the linked PIL by machine. The linker places every instance in a
+/// namespace named after its location, e.g. `main_memory` for `Main::memory`.
+pub fn machine_stats<T>(
+    asm: &AnalysisASMFile,
+    pil: &Analyzed<T>,
+) -> BTreeMap<String, MachineStats> {
+    let mut namespaces = BTreeMap::new();
+    if let Some((path, machine)) = asm.machines().find(|(path, _)| {
+        path.relative_to(&AbsoluteSymbolPath::default()).to_string() == ENTRY_MACHINE
+    }) {
+        collect_instances(
+            asm,
+            "main".to_string(),
+            path.to_string(),
+            machine,
+            &mut namespaces,
+            &mut vec![],
+        );
+    }
+
+    let mut stats: BTreeMap<String, MachineStats> = BTreeMap::new();
+    for machine in namespaces.values() {
+        stats.entry(machine.clone()).or_default().instances += 1;
+    }
+
+    for (name, (symbol, _)) in &pil.definitions {
+        let Some(machine) = namespace_of(name).and_then(|ns| namespaces.get(ns)) else {
+            continue;
+        };
+        let entry = stats.entry(machine.clone()).or_default();
+
+        let count = symbol.length.unwrap_or(1) as usize;
+        match symbol.kind {
+            SymbolKind::Poly(PolynomialType::Committed) => entry.witness_columns += count,
+            SymbolKind::Poly(PolynomialType::Constant) => entry.fixed_columns += count,
+            _ => continue,
+        }
+
+        if let Some(degree) = &symbol.degree {
+            entry.min_degree = Some(entry.min_degree.map_or(degree.min, |d| d.min(degree.min)));
+            entry.max_degree = Some(entry.max_degree.map_or(degree.max, |d| d.max(degree.max)));
+        }
+    }
+
+    for identity in &pil.identities {
+        // Identities are not namespaced, so they are attributed to the machine
+        // owning the first column they reference, which is the caller for links.
+        let Some(machine) = identity
+            .all_children()
+            .find_map(|e| match e {
+                AlgebraicExpression::Reference(reference) => namespace_of(&reference.name),
+                _ => None,
+            })
+            .and_then(|ns| namespaces.get(ns))
+        else {
+            continue;
+        };
+        let entry = stats.entry(machine.clone()).or_default();
+
+        match identity {
+            Identity::Polynomial(_) => entry.identities += 1,
+            Identity::Lookup(_) | Identity::PhantomLookup(_) => entry.lookups += 1,
+            Identity::Permutation(_) | Identity::PhantomPermutation(_) => entry.permutations += 1,
+            _ => {}
+        }
+    }
+
+    stats
+}
+
+fn collect_instances(
+    asm: &AnalysisASMFile,
+    location: String,
+    machine_name: String,
+    machine: &Machine,
+    namespaces: &mut BTreeMap<String, String>,
+    ancestors: &mut Vec<String>,
+) {
+    // Guards against machines instantiating themselves while being edited.
+    if ancestors.contains(&machine_name) {
+        return;
+    }
+    namespaces.insert(location.clone(), machine_name.clone());
+    ancestors.push(machine_name);
+
+    for submachine in &machine.submachines {
+        let ty = submachine.ty.to_string();
+        if let Some((_, child)) = asm.machines().find(|(path, _)| path.to_string() == ty) {
+            collect_instances(
+                asm,
+                format!("{location}_{}", submachine.name),
+                ty,
+                child,
+                namespaces,
+                ancestors,
+            );
+        }
+    }
+
+    ancestors.pop();
+}
+
+fn namespace_of(name: &str) -> Option<&str> {
+    name.rsplit_once("::").map(|(namespace, _)| namespace)
+}
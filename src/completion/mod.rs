@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+
+use crate::symbol::{SemanticIndex, Symbol, SymbolDetails, SymbolKind};
+use tower_lsp::lsp_types::*;
+
+pub struct CompletionProvider<'a> {
+    semantic_index: &'a SemanticIndex,
+}
+
+impl<'a> CompletionProvider<'a> {
+    pub fn new(semantic_index: &'a SemanticIndex) -> Self {
+        Self { semantic_index }
+    }
+
+    /// The symbols declared in the document, with their doc comments. Clients
+    /// filter them by the word being typed.
+    pub fn get_completions(&self) -> Vec<CompletionItem> {
+        let mut items = BTreeMap::new();
+        for (id, symbol) in &self.semantic_index.symbols {
+            if self.semantic_index.declaration_of(*id) != *id {
+                continue;
+            }
+            let Some(kind) = completion_kind(&symbol.kind) else {
+                continue;
+            };
+            let label = local_name(&symbol.name);
+            // Machines are declared under their full and their short name.
+            items
+                .entry((label.to_string(), symbol.span.start))
+                .or_insert_with(|| CompletionItem {
+                    label: label.to_string(),
+                    kind: Some(kind),
+                    detail: detail(symbol),
+                    documentation: self.semantic_index.doc(*id).map(|doc| {
+                        Documentation::MarkupContent(MarkupContent {
+                            kind: MarkupKind::Markdown,
+                            value: doc.to_string(),
+                        })
+                    }),
+                    ..Default::default()
+                });
+        }
+
+        items.into_values().collect()
+    }
+}
+
+fn completion_kind(kind: &SymbolKind) -> Option<CompletionItemKind> {
+    let kind = match kind {
+        SymbolKind::Machine | SymbolKind::Struct => CompletionItemKind::STRUCT,
+        SymbolKind::Instruction | SymbolKind::Operation => CompletionItemKind::METHOD,
+        SymbolKind::Function => CompletionItemKind::FUNCTION,
+        SymbolKind::Register
+        | SymbolKind::FixedColumn
+        | SymbolKind::WitnessColumn
+        | SymbolKind::Intermediate
+        | SymbolKind::Public => CompletionItemKind::VARIABLE,
+        SymbolKind::Submachine => CompletionItemKind::FIELD,
+        SymbolKind::Module | SymbolKind::Namespace => CompletionItemKind::MODULE,
+        SymbolKind::Import => CompletionItemKind::REFERENCE,
+        SymbolKind::Constant | SymbolKind::Definition => CompletionItemKind::CONSTANT,
+        SymbolKind::Label => CompletionItemKind::REFERENCE,
+        SymbolKind::Enum => CompletionItemKind::ENUM,
+        SymbolKind::EnumVariant => CompletionItemKind::ENUM_MEMBER,
+        SymbolKind::Trait => CompletionItemKind::INTERFACE,
+        // Links and trait implementations are not referred to by name.
+        SymbolKind::Link | SymbolKind::TraitImpl => return None,
+    };
+    Some(kind)
+}
+
+/// A one line description of the symbol, e.g. its signature or declaration.
+fn detail(symbol: &Symbol) -> Option<String> {
+    let detail = match &symbol.details {
+        SymbolDetails::Machine { summary, .. } if !summary.params.is_empty() => {
+            format!("machine {}({})", local_name(&symbol.name), summary.params)
+        }
+        SymbolDetails::Machine { .. } => format!("machine {}", local_name(&symbol.name)),
+        SymbolDetails::Register { type_info } if !type_info.is_empty() => type_info.clone(),
+        SymbolDetails::Callable { inputs, outputs } if outputs.is_empty() => {
+            format!("{}({})", symbol.name, inputs)
+        }
+        SymbolDetails::Callable { inputs, outputs } => {
+            format!("{}({}) -> {}", symbol.name, inputs, outputs)
+        }
+        SymbolDetails::Submachine { machine_type, .. } => machine_type.clone(),
+        SymbolDetails::Module { path } => path.clone(),
+        SymbolDetails::Import { target } => target.clone(),
+        SymbolDetails::Constant {
+            type_info: Some(type_info),
+            ..
+        } => type_info.clone(),
+        SymbolDetails::Column { declaration } => declaration.clone(),
+        SymbolDetails::Declaration { declaration, .. } => declaration.clone(),
+        SymbolDetails::Label { function } => format!("label in {function}"),
+        _ => return None,
+    };
+    Some(detail)
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit_once("::").map_or(name, |(_, local)| local)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operation(name: &str, start: usize) -> Symbol {
+        Symbol {
+            kind: SymbolKind::Operation,
+            name: name.to_string(),
+            span: start..start + name.len(),
+            details: SymbolDetails::Callable {
+                inputs: "a, b".to_string(),
+                outputs: "c".to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn declarations_are_offered_with_their_docs() {
+        let mut index = SemanticIndex::new();
+        let add = index.add_symbol(operation("add", 10));
+        index.add_doc(add, "Adds two numbers.".to_string());
+        index.add_occurrence(operation("add", 40), add);
+        index.add_symbol(operation("sub", 20));
+
+        let items = CompletionProvider::new(&index).get_completions();
+
+        let labels: Vec<_> = items.iter().map(|item| item.label.as_str()).collect();
+        assert_eq!(labels, vec!["add", "sub"]);
+        assert_eq!(items[0].detail.as_deref(), Some("add(a, b) -> c"));
+        assert_eq!(
+            items[0].documentation,
+            Some(Documentation::MarkupContent(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: "Adds two numbers.".to_string(),
+            }))
+        );
+        assert_eq!(items[1].documentation, None);
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use powdr_number::FieldElement;
+use serde::Serialize;
+use tower_lsp::lsp_types::Url;
+
+use crate::project::ProjectCache;
+use crate::symbol::{Symbol, SymbolId, SymbolKind, SymbolUse};
+use crate::workspace::{find_source_files, load_document};
+
+const USAGE: &str = "Usage: powdr-lsp index <path>";
+
+#[derive(Serialize)]
+pub struct IndexDump<'a> {
+    pub documents: Vec<DocumentDump<'a>>,
+    pub symbol_locations: BTreeMap<&'a str, Vec<SymbolLocation<'a>>>,
+}
+
+#[derive(Serialize)]
+pub struct DocumentDump<'a> {
+    pub uri: &'a Url,
+    pub symbols: Vec<SymbolEntry<'a>>,
+    pub uses: &'a [SymbolUse],
+}
+
+#[derive(Serialize)]
+pub struct SymbolEntry<'a> {
+    pub id: SymbolId,
+    #[serde(flatten)]
+    pub symbol: &'a Symbol,
+}
+
+#[derive(Serialize)]
+pub struct SymbolLocation<'a> {
+    pub uri: &'a Url,
+    pub kind: &'a SymbolKind,
+}
+
+/// Entry point of `powdr-lsp index`, returns the process exit code.
+pub fn run<T: FieldElement>(args: &[String]) -> i32 {
+    let [path] = args else {
+        eprintln!("{USAGE}");
+        return 2;
+    };
+
+    let cache = match index_path::<T>(Path::new(path)) {
+        Ok(cache) => cache,
+        Err(e) => {
+            eprintln!("error: {e}");
+            return 2;
+        }
+    };
+
+    println!("{}", serde_json::to_string_pretty(&dump(&cache)).unwrap());
+    0
+}
+
+/// Indexes a single file, or every source file below a directory.
+pub fn index_path<T: FieldElement>(path: &Path) -> io::Result<ProjectCache> {
+    let files = if path.is_dir() {
+        find_source_files(path)?
+    } else {
+        vec![PathBuf::from(path)]
+    };
+
+    let mut cache = ProjectCache::new();
+    for file in files {
+        let (uri, doc) = load_document::<T>(&file)?;
+        cache.update_document(uri, doc);
+    }
+
+    Ok(cache)
+}
+
+/// Builds a deterministic view of the cache: documents, symbols and locations are sorted.
+pub fn dump(cache: &ProjectCache) -> IndexDump<'_> {
+    let mut documents: Vec<_> = cache
+        .documents
+        .iter()
+        .map(|(uri, doc)| {
+            let mut symbols: Vec<_> = doc
+                .semantic_index
+                .symbols
+                .iter()
+                .map(|(id, symbol)| SymbolEntry { id: *id, symbol })
+                .collect();
+            symbols.sort_by_key(|entry| entry.id);
+
+            DocumentDump {
+                uri,
+                symbols,
+                uses: &doc.semantic_index.uses,
+            }
+        })
+        .collect();
+    documents.sort_by(|a, b| a.uri.as_str().cmp(b.uri.as_str()));
+
+    let symbol_locations = cache
+        .symbol_locations
+        .iter()
+        .map(|(name, locations)| {
+            let mut locations: Vec<_> = locations
+                .iter()
+                .map(|(uri, kind)| SymbolLocation { uri, kind })
+                .collect();
+            locations.sort_by(|a, b| a.uri.as_str().cmp(b.uri.as_str()));
+            (name.as_str(), locations)
+        })
+        .collect();
+
+    IndexDump {
+        documents,
+        symbol_locations,
+    }
+}
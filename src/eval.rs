@@ -0,0 +1,500 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::thread;
+
+use powdr_ast::parsed::visitor::Children;
+use powdr_ast::parsed::{
+    BinaryOperation, BinaryOperator, BlockExpression, Expression, FunctionCall, IfExpression,
+    IndexAccess, LambdaExpression, PILFile, Pattern, PilStatement, SourceReference, UnaryOperation,
+    UnaryOperator,
+};
+use powdr_number::BigInt;
+use powdr_parser_util::SourceRef;
+
+/// Evaluation steps the expressions of a file may take before the analysis is
+/// stopped, the analyzer itself cannot be interrupted.
+pub const MAX_STEPS: usize = 1_000_000;
+
+/// Nested function calls, deeper recursion is treated like running out of steps.
+const MAX_DEPTH: usize = 2_000;
+
+/// Every nested call takes a few stack frames of the evaluator.
+const STACK_SIZE: usize = 64 * 1024 * 1024;
+
+/// The definition an evaluation was in when it ran out of steps.
+#[derive(Debug, PartialEq)]
+pub struct Exhausted {
+    pub name: String,
+    pub source: SourceRef,
+}
+
+/// Evaluates the expressions the PIL analyzer evaluates, namespace degrees,
+/// array lengths and top level constraints, counting evaluation steps. Only
+/// integers, booleans, arrays and closures over definitions of this file are
+/// evaluated; an expression using anything else is left to the analyzer.
+pub fn check_steps(pil: &PILFile) -> Result<(), Exhausted> {
+    // Without a thread of its own, leave the file to the analyzer.
+    with_stack(|| evaluate_roots(pil)).unwrap_or(Ok(()))
+}
+
+/// Evaluates the integer constant `name` among `definitions`, absolute names
+/// with their defining expressions, within the step limit.
+pub fn evaluate_constant(definitions: Vec<(String, &Expression)>, name: &str) -> Option<BigInt> {
+    with_stack(|| {
+        let definitions = definitions
+            .into_iter()
+            .map(|(name, value)| {
+                let definition = Definition {
+                    value,
+                    source: value.source_reference(),
+                    namespace: String::new(),
+                };
+                (name.trim_start_matches("::").to_string(), definition)
+            })
+            .collect();
+        match Evaluator::new(definitions).definition(name) {
+            Ok(Value::Int(value)) => Some(value),
+            _ => None,
+        }
+    })
+    .flatten()
+}
+
+/// Runs `f` on a thread with room for deep recursion.
+fn with_stack<R: Send>(f: impl FnOnce() -> R + Send) -> Option<R> {
+    thread::scope(|scope| {
+        let worker = thread::Builder::new()
+            .name("powdr-steps".to_string())
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, f)
+            .ok()?;
+        Some(
+            worker
+                .join()
+                .unwrap_or_else(|e| std::panic::resume_unwind(e)),
+        )
+    })
+}
+
+fn evaluate_roots(pil: &PILFile) -> Result<(), Exhausted> {
+    let mut evaluator = Evaluator::new(pil_definitions(pil));
+    let mut namespace = String::new();
+
+    for statement in &pil.0 {
+        if let PilStatement::Namespace(_, path, ..) = statement {
+            namespace = path.to_string();
+        }
+        // Definitions are evaluated when a root refers to them.
+        if matches!(statement, PilStatement::LetStatement(..)) {
+            continue;
+        }
+
+        let source = statement.source_reference();
+        for root in statement.children() {
+            evaluator.namespace = namespace.clone();
+            match evaluator.evaluate(root, &Scope::default()) {
+                Ok(_) | Err(Stop::Unsupported) => {}
+                Err(Stop::Exhausted) => {
+                    return Err(evaluator.stopped_in.take().unwrap_or_else(|| Exhausted {
+                        name: statement
+                            .to_string()
+                            .lines()
+                            .next()
+                            .unwrap_or_default()
+                            .to_string(),
+                        source: source.clone(),
+                    }));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Clone)]
+enum Value<'a> {
+    Int(BigInt),
+    Bool(bool),
+    Array(Rc<Vec<Value<'a>>>),
+    Closure(Rc<Closure<'a>>),
+}
+
+struct Closure<'a> {
+    lambda: &'a LambdaExpression<Expression>,
+    scope: Scope<'a>,
+    /// The definition the closure was written in.
+    definition: Rc<str>,
+    namespace: Rc<str>,
+}
+
+/// Local variables, innermost last.
+#[derive(Clone, Default)]
+struct Scope<'a>(Vec<(&'a str, Value<'a>)>);
+
+impl<'a> Scope<'a> {
+    fn get(&self, name: &str) -> Option<&Value<'a>> {
+        self.0
+            .iter()
+            .rev()
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| v)
+    }
+}
+
+enum Stop {
+    /// The expression uses something not evaluated here.
+    Unsupported,
+    Exhausted,
+}
+
+struct Definition<'a> {
+    value: &'a Expression,
+    source: &'a SourceRef,
+    namespace: String,
+}
+
+/// The `let` definitions of a PIL file by absolute name.
+fn pil_definitions(pil: &PILFile) -> HashMap<String, Definition<'_>> {
+    let mut definitions = HashMap::new();
+    let mut namespace = String::new();
+    for statement in &pil.0 {
+        match statement {
+            PilStatement::Namespace(_, path, ..) => namespace = path.to_string(),
+            PilStatement::LetStatement(source, name, _, Some(value)) => {
+                let name = if namespace.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{namespace}::{name}")
+                };
+                definitions.insert(
+                    name,
+                    Definition {
+                        value,
+                        source,
+                        namespace: namespace.clone(),
+                    },
+                );
+            }
+            _ => {}
+        }
+    }
+    definitions
+}
+
+struct Evaluator<'a> {
+    definitions: HashMap<String, Definition<'a>>,
+    values: HashMap<String, Value<'a>>,
+    steps: usize,
+    depth: usize,
+    /// Definitions being evaluated, innermost last.
+    stack: Vec<(Rc<str>, &'a SourceRef)>,
+    namespace: String,
+    stopped_in: Option<Exhausted>,
+}
+
+impl<'a> Evaluator<'a> {
+    fn new(definitions: HashMap<String, Definition<'a>>) -> Self {
+        Self {
+            definitions,
+            values: HashMap::new(),
+            steps: 0,
+            depth: 0,
+            stack: vec![],
+            namespace: String::new(),
+            stopped_in: None,
+        }
+    }
+
+    fn step(&mut self, cost: usize) -> Result<(), Stop> {
+        self.steps += cost;
+        if self.steps > MAX_STEPS || self.depth > MAX_DEPTH {
+            return Err(self.exhausted());
+        }
+        Ok(())
+    }
+
+    fn exhausted(&mut self) -> Stop {
+        if self.stopped_in.is_none() {
+            self.stopped_in = self.stack.last().map(|(name, source)| Exhausted {
+                name: name.to_string(),
+                source: (*source).clone(),
+            });
+        }
+        Stop::Exhausted
+    }
+
+    fn evaluate(&mut self, expr: &'a Expression, scope: &Scope<'a>) -> Result<Value<'a>, Stop> {
+        self.step(1)?;
+        match expr {
+            Expression::Number(_, number) => Ok(Value::Int(BigInt::from(number.value.clone()))),
+            Expression::Reference(_, reference) => {
+                let path = reference.path.to_string();
+                match scope.get(&path) {
+                    Some(value) => Ok(value.clone()),
+                    None => self.definition(&path),
+                }
+            }
+            Expression::LambdaExpression(_, lambda) => {
+                let (definition, _) = self.stack.last().ok_or(Stop::Unsupported)?;
+                Ok(Value::Closure(Rc::new(Closure {
+                    lambda,
+                    scope: scope.clone(),
+                    definition: definition.clone(),
+                    namespace: Rc::from(self.namespace.as_str()),
+                })))
+            }
+            Expression::ArrayLiteral(_, array) => {
+                let items = array
+                    .items
+                    .iter()
+                    .map(|item| self.evaluate(item, scope))
+                    .collect::<Result<_, _>>()?;
+                Ok(Value::Array(Rc::new(items)))
+            }
+            Expression::UnaryOperation(_, UnaryOperation { op, expr }) => {
+                match (op, self.evaluate(expr, scope)?) {
+                    (UnaryOperator::Minus, Value::Int(value)) => Ok(Value::Int(-value)),
+                    (UnaryOperator::LogicalNot, Value::Bool(value)) => Ok(Value::Bool(!value)),
+                    _ => Err(Stop::Unsupported),
+                }
+            }
+            Expression::BinaryOperation(_, BinaryOperation { left, op, right }) => {
+                self.binary(left, *op, right, scope)
+            }
+            Expression::IndexAccess(_, IndexAccess { array, index }) => {
+                let (Value::Array(items), Value::Int(index)) =
+                    (self.evaluate(array, scope)?, self.evaluate(index, scope)?)
+                else {
+                    return Err(Stop::Unsupported);
+                };
+                usize::try_from(&index)
+                    .ok()
+                    .and_then(|index| items.get(index).cloned())
+                    .ok_or(Stop::Unsupported)
+            }
+            Expression::FunctionCall(
+                _,
+                FunctionCall {
+                    function,
+                    arguments,
+                },
+            ) => {
+                let Value::Closure(closure) = self.evaluate(function, scope)? else {
+                    return Err(Stop::Unsupported);
+                };
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| self.evaluate(argument, scope))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.call(&closure, arguments)
+            }
+            Expression::IfExpression(
+                _,
+                IfExpression {
+                    condition,
+                    body,
+                    else_body,
+                },
+            ) => match self.evaluate(condition, scope)? {
+                Value::Bool(true) => self.evaluate(body, scope),
+                Value::Bool(false) => self.evaluate(else_body, scope),
+                _ => Err(Stop::Unsupported),
+            },
+            Expression::BlockExpression(_, BlockExpression { statements, expr })
+                if statements.is_empty() =>
+            {
+                match expr {
+                    Some(expr) => self.evaluate(expr, scope),
+                    None => Err(Stop::Unsupported),
+                }
+            }
+            _ => Err(Stop::Unsupported),
+        }
+    }
+
+    /// Resolves `path` like the analyzer, relative to the namespace first.
+    fn definition(&mut self, path: &str) -> Result<Value<'a>, Stop> {
+        match path {
+            "true" => return Ok(Value::Bool(true)),
+            "false" => return Ok(Value::Bool(false)),
+            _ => {}
+        }
+        // Asm references are absolute paths.
+        let path = path.trim_start_matches("::");
+
+        let relative = (!self.namespace.is_empty()).then(|| format!("{}::{path}", self.namespace));
+        let name = relative
+            .into_iter()
+            .chain([path.to_string()])
+            .find(|name| self.definitions.contains_key(name))
+            .ok_or(Stop::Unsupported)?;
+        if let Some(value) = self.values.get(&name) {
+            return Ok(value.clone());
+        }
+        // A constant defined in terms of itself never finishes.
+        if self.stack.iter().any(|(n, _)| **n == name) {
+            return Err(self.exhausted());
+        }
+
+        let definition = &self.definitions[&name];
+        let (value, source) = (definition.value, definition.source);
+        let namespace = std::mem::replace(&mut self.namespace, definition.namespace.to_string());
+        self.stack.push((Rc::from(name.as_str()), source));
+        let result = self.evaluate(value, &Scope::default());
+        self.stack.pop();
+        self.namespace = namespace;
+
+        let value = result?;
+        self.values.insert(name, value.clone());
+        Ok(value)
+    }
+
+    fn call(
+        &mut self,
+        closure: &Closure<'a>,
+        arguments: Vec<Value<'a>>,
+    ) -> Result<Value<'a>, Stop> {
+        if closure.lambda.params.len() != arguments.len() {
+            return Err(Stop::Unsupported);
+        }
+        let mut scope = closure.scope.clone();
+        for (param, argument) in closure.lambda.params.iter().zip(arguments) {
+            let Pattern::Variable(_, name) = param else {
+                return Err(Stop::Unsupported);
+            };
+            scope.0.push((name.as_str(), argument));
+        }
+
+        let source = self.definitions[&*closure.definition].source;
+        let namespace = std::mem::replace(&mut self.namespace, closure.namespace.to_string());
+        self.stack.push((closure.definition.clone(), source));
+        self.depth += 1;
+        let result = self
+            .step(0)
+            .and_then(|_| self.evaluate(&closure.lambda.body, &scope));
+        self.depth -= 1;
+        self.stack.pop();
+        self.namespace = namespace;
+        result
+    }
+
+    fn binary(
+        &mut self,
+        left: &'a Expression,
+        op: BinaryOperator,
+        right: &'a Expression,
+        scope: &Scope<'a>,
+    ) -> Result<Value<'a>, Stop> {
+        let left = self.evaluate(left, scope)?;
+        // Only evaluate the right side when it decides the result.
+        match (op, &left) {
+            (BinaryOperator::LogicalOr, Value::Bool(true)) => return Ok(Value::Bool(true)),
+            (BinaryOperator::LogicalAnd, Value::Bool(false)) => return Ok(Value::Bool(false)),
+            (BinaryOperator::LogicalOr | BinaryOperator::LogicalAnd, Value::Bool(_)) => {
+                return self.evaluate(right, scope);
+            }
+            _ => {}
+        }
+
+        let (Value::Int(left), Value::Int(right)) = (left, self.evaluate(right, scope)?) else {
+            return Err(Stop::Unsupported);
+        };
+        let zero = BigInt::from(0);
+        let value = match op {
+            BinaryOperator::Add => left + right,
+            BinaryOperator::Sub => left - right,
+            BinaryOperator::Mul => left * right,
+            BinaryOperator::Div if right != zero => left / right,
+            BinaryOperator::Mod if right != zero => left % right,
+            BinaryOperator::Pow => {
+                let exponent = u32::try_from(&right).map_err(|_| self.exhausted())?;
+                // Multiplying big integers costs one step per 64 bits of the result.
+                self.step(left.bits() as usize * exponent as usize / 64)?;
+                left.pow(exponent)
+            }
+            BinaryOperator::BinaryAnd => left & right,
+            BinaryOperator::BinaryXor => left ^ right,
+            BinaryOperator::BinaryOr => left | right,
+            BinaryOperator::ShiftLeft | BinaryOperator::ShiftRight => {
+                let shift = usize::try_from(&right).map_err(|_| self.exhausted())?;
+                self.step(shift / 64)?;
+                if op == BinaryOperator::ShiftLeft {
+                    left << shift
+                } else {
+                    left >> shift
+                }
+            }
+            BinaryOperator::Less => return Ok(Value::Bool(left < right)),
+            BinaryOperator::LessEqual => return Ok(Value::Bool(left <= right)),
+            BinaryOperator::Equal => return Ok(Value::Bool(left == right)),
+            BinaryOperator::NotEqual => return Ok(Value::Bool(left != right)),
+            BinaryOperator::GreaterEqual => return Ok(Value::Bool(left >= right)),
+            BinaryOperator::Greater => return Ok(Value::Bool(left > right)),
+            _ => return Err(Stop::Unsupported),
+        };
+        Ok(Value::Int(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(text: &str) -> Result<(), Exhausted> {
+        check_steps(&powdr_parser::parse(None, text).unwrap())
+    }
+
+    #[test]
+    fn endless_recursion_stops_in_its_definition() {
+        let text = "namespace Main(8);\n\
+            let f = |n| f(n + 1);\n\
+            let N = f(0);\n\
+            col witness x[N];\n";
+        let exhausted = check(text).unwrap_err();
+        assert_eq!(exhausted.name, "Main::f");
+        assert!(text[exhausted.source.start..].starts_with("let f ="));
+    }
+
+    #[test]
+    fn long_loop_stops_in_the_loop() {
+        let text = "let count = |n, acc| if n == 0 { acc } else { count(n - 1, acc + 1) };\n\
+            let N = count(100000000, 0);\n\
+            namespace Main(N);\n";
+        assert_eq!(check(text).unwrap_err().name, "count");
+    }
+
+    #[test]
+    fn bounded_evaluation_passes() {
+        let text = "let fib = |n| if n < 2 { n } else { fib(n - 1) + fib(n - 2) };\n\
+            let N = 2**fib(4);\n\
+            namespace Main(N);\n\
+            col witness x[N - 6];\n";
+        assert_eq!(check(text), Ok(()));
+    }
+
+    #[test]
+    fn columns_are_left_to_the_analyzer() {
+        let text = "namespace Main(8);\ncol witness x;\nx' = x + 1;\n";
+        assert_eq!(check(text), Ok(()));
+    }
+
+    #[test]
+    fn constants_are_evaluated_through_their_references() {
+        let pil = powdr_parser::parse(None, "let K = 4;\nlet N = 2**K - 1;\n").unwrap();
+        let definitions = pil
+            .0
+            .iter()
+            .filter_map(|statement| match statement {
+                PilStatement::LetStatement(_, name, _, Some(value)) => {
+                    Some((format!("::{name}"), value))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            evaluate_constant(definitions, "::N"),
+            Some(BigInt::from(15))
+        );
+    }
+}
@@ -0,0 +1,346 @@
+use crate::parser::convert_position;
+use crate::span::{Span, code_tokens};
+use powdr_ast::parsed::asm::{ASMModule, MachineStatement, Module, ModuleStatement, SymbolValue};
+use powdr_ast::parsed::{PilStatement, SourceReference};
+use powdr_parser_util::SourceRef;
+use tower_lsp::lsp_types::*;
+
+pub struct FoldingRangeProvider<'a> {
+    text: &'a str,
+    /// Code tokens in source order, see `code_tokens`.
+    tokens: Vec<Span>,
+}
+
+/// A `machine` or `mod` item with a braced body.
+struct Block {
+    /// Start of the declaring keyword.
+    header: usize,
+    /// End of the last token inside the braces.
+    body_end: usize,
+    /// End of the closing brace.
+    end: usize,
+}
+
+impl<'a> FoldingRangeProvider<'a> {
+    pub fn new(text: &'a str) -> Self {
+        Self {
+            text,
+            tokens: code_tokens(text),
+        }
+    }
+
+    pub fn get_folding_ranges(&self, uri: &Url) -> Vec<FoldingRange> {
+        let mut ranges = Vec::new();
+
+        // Ranges come from the parsed (not yet resolved) AST, so only items written
+        // in this file are folded.
+        if uri.path().ends_with(".asm") {
+            if let Ok(program) = powdr_parser::parse_asm(None, self.text) {
+                self.fold_module(&program.main, &mut 0, &mut ranges);
+            }
+        } else if let Ok(pil) = powdr_parser::parse(None, self.text) {
+            self.fold_pil_file(&pil.0, &mut ranges);
+        }
+
+        self.fold_comments(&mut ranges);
+
+        ranges
+    }
+
+    /// Folds every item of the module. Machines and modules carry no source
+    /// reference, their blocks are looked up by keyword and name from `cursor`
+    /// on, which moves past each item in source order.
+    fn fold_module(&self, module: &ASMModule, cursor: &mut usize, ranges: &mut Vec<FoldingRange>) {
+        for statement in &module.statements {
+            let ModuleStatement::SymbolDefinition(definition) = statement else {
+                continue;
+            };
+
+            match &definition.value {
+                SymbolValue::Machine(machine) => {
+                    let Some(block) = self.block(*cursor, "machine", &definition.name) else {
+                        continue;
+                    };
+                    for statement in &machine.statements {
+                        self.fold_machine_statement(statement, ranges);
+                    }
+                    self.push_range(
+                        block.header..block.body_end,
+                        FoldingRangeKind::Region,
+                        ranges,
+                    );
+                    *cursor = block.end;
+                }
+                SymbolValue::Module(Module::Local(inner)) => {
+                    let Some(block) = self.block(*cursor, "mod", &definition.name) else {
+                        continue;
+                    };
+                    *cursor = block.header;
+                    self.fold_module(inner, cursor, ranges);
+                    self.push_range(
+                        block.header..block.body_end,
+                        FoldingRangeKind::Region,
+                        ranges,
+                    );
+                    *cursor = block.end;
+                }
+                SymbolValue::Expression(expr) => {
+                    let span = self.to_span(expr.e.source_reference());
+                    *cursor = (*cursor).max(span.end);
+                    self.fold_statement_span(span, ranges);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn fold_machine_statement(&self, statement: &MachineStatement, ranges: &mut Vec<FoldingRange>) {
+        match statement {
+            MachineStatement::InstructionDeclaration(source, _, instruction) => {
+                if let Some(last) = instruction.body.0.last() {
+                    self.fold_body(
+                        self.to_span(source),
+                        self.to_span(last.source_reference()),
+                        ranges,
+                    );
+                }
+            }
+            MachineStatement::FunctionDeclaration(source, _, _, statements) => {
+                if let Some(last) = statements.last() {
+                    self.fold_body(
+                        self.to_span(source),
+                        self.to_span(last.source_reference()),
+                        ranges,
+                    );
+                }
+            }
+            MachineStatement::Pil(_, statement) => {
+                self.fold_pil_statement(statement, ranges);
+            }
+            MachineStatement::Submachine(..)
+            | MachineStatement::RegisterDeclaration(..)
+            | MachineStatement::LinkDeclaration(..)
+            | MachineStatement::OperationDeclaration(..) => {}
+        }
+    }
+
+    fn fold_pil_file(&self, statements: &[PilStatement], ranges: &mut Vec<FoldingRange>) {
+        let mut namespace: Option<Span> = None;
+
+        for statement in statements {
+            if let PilStatement::Namespace(source, ..) = statement {
+                if let Some(span) = namespace.take() {
+                    self.push_range(span, FoldingRangeKind::Region, ranges);
+                }
+                namespace = Some(self.to_span(source));
+                continue;
+            }
+
+            let span = self.fold_pil_statement(statement, ranges);
+            if let Some(namespace) = namespace.as_mut() {
+                namespace.end = namespace.end.max(span.end);
+            }
+        }
+
+        if let Some(span) = namespace {
+            self.push_range(span, FoldingRangeKind::Region, ranges);
+        }
+    }
+
+    fn fold_pil_statement(&self, statement: &PilStatement, ranges: &mut Vec<FoldingRange>) -> Span {
+        self.fold_statement_span(self.to_span(statement.source_reference()), ranges)
+    }
+
+    /// Folds a single statement if it spans more than one line.
+    fn fold_statement_span(&self, span: Span, ranges: &mut Vec<FoldingRange>) -> Span {
+        self.push_range(span.clone(), FoldingRangeKind::Region, ranges);
+        span
+    }
+
+    /// Folds a declaration from its header down to the last statement of its body.
+    fn fold_body(&self, declaration: Span, last: Span, ranges: &mut Vec<FoldingRange>) {
+        self.push_range(
+            declaration.start..last.end,
+            FoldingRangeKind::Region,
+            ranges,
+        );
+    }
+
+    /// The block of the item `keyword name` declared at or after `from`. Items
+    /// without a body, e.g. `mod name;`, have none.
+    fn block(&self, from: usize, keyword: &str, name: &str) -> Option<Block> {
+        let tokens = &self.tokens[self.tokens.partition_point(|token| token.start < from)..];
+        let header = tokens.windows(2).position(|pair| {
+            self.text[pair[0].clone()] == *keyword && self.text[pair[1].clone()] == *name
+        })?;
+
+        let mut depth = 0;
+        for (i, token) in tokens.iter().enumerate().skip(header + 2) {
+            match &self.text[token.clone()] {
+                "{" => depth += 1,
+                "}" if depth == 1 => {
+                    return Some(Block {
+                        header: tokens[header].start,
+                        body_end: tokens[i - 1].end,
+                        end: token.end,
+                    });
+                }
+                "}" => depth -= 1,
+                ";" if depth == 0 => return None,
+                _ => {}
+            }
+        }
+        None
+    }
+
+    fn fold_comments(&self, ranges: &mut Vec<FoldingRange>) {
+        let mut run: Option<(u32, u32)> = None;
+        let mut in_block = false;
+
+        for (line, content) in self.text.lines().enumerate() {
+            let line = line as u32;
+            let trimmed = content.trim_start();
+
+            let is_comment = if in_block {
+                in_block = !trimmed.contains("*/");
+                true
+            } else if trimmed.starts_with("/*") {
+                in_block = !trimmed.contains("*/");
+                true
+            } else {
+                trimmed.starts_with("//")
+            };
+
+            run = match (run, is_comment) {
+                (Some((start, _)), true) => Some((start, line)),
+                (None, true) => Some((line, line)),
+                (Some((start, end)), false) => {
+                    push_lines(start, end, FoldingRangeKind::Comment, ranges);
+                    None
+                }
+                (None, false) => None,
+            };
+        }
+
+        if let Some((start, end)) = run {
+            push_lines(start, end, FoldingRangeKind::Comment, ranges);
+        }
+    }
+
+    fn push_range(&self, span: Span, kind: FoldingRangeKind, ranges: &mut Vec<FoldingRange>) {
+        let start = convert_position(span.start, self.text).line;
+        let end = convert_position(span.end, self.text).line;
+        push_lines(start, end, kind, ranges);
+    }
+
+    fn to_span(&self, source: &SourceRef) -> Span {
+        let start = source.start.min(self.text.len());
+        start..source.end.clamp(start, self.text.len())
+    }
+}
+
+fn push_lines(start: u32, end: u32, kind: FoldingRangeKind, ranges: &mut Vec<FoldingRange>) {
+    if end > start {
+        ranges.push(FoldingRange {
+            start_line: start,
+            end_line: end,
+            kind: Some(kind),
+            ..Default::default()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region_lines(text: &str) -> Vec<(u32, u32)> {
+        regions_in("file:///test.asm", text)
+    }
+
+    fn regions_in(uri: &str, text: &str) -> Vec<(u32, u32)> {
+        let uri = Url::parse(uri).unwrap();
+        let mut lines: Vec<_> = FoldingRangeProvider::new(text)
+            .get_folding_ranges(&uri)
+            .into_iter()
+            .filter(|range| range.kind == Some(FoldingRangeKind::Region))
+            .map(|range| (range.start_line, range.end_line))
+            .collect();
+        lines.sort();
+        lines
+    }
+
+    #[test]
+    fn machine_folds_from_header_to_last_statement() {
+        let text = "\
+machine Main {
+    reg pc[@pc];
+    reg A;
+
+    function main {
+        A <=X= 1;
+        return;
+    }
+}
+";
+        assert_eq!(region_lines(text), vec![(0, 7), (4, 6)]);
+    }
+
+    #[test]
+    fn module_with_only_imports_is_folded() {
+        let text = "\
+mod utils {
+    use std::machines::Byte;
+    use std::machines::Byte2;
+}
+";
+        assert_eq!(region_lines(text), vec![(0, 2)]);
+    }
+
+    #[test]
+    fn braces_in_comments_and_strings_are_ignored() {
+        let text = "\
+// a stray { before the machine
+machine Main {
+    // and a } inside it
+    reg pc[@pc];
+    reg A;
+}
+";
+        assert_eq!(region_lines(text), vec![(1, 4)]);
+    }
+
+    #[test]
+    fn lambda_braces_stay_inside_their_machine() {
+        let text = "\
+machine Main {
+    col fixed STEP(i) { i };
+    reg pc[@pc];
+    reg A;
+}
+machine Other {
+    reg pc[@pc];
+    reg B;
+}
+";
+        assert_eq!(region_lines(text), vec![(0, 3), (5, 7)]);
+    }
+
+    #[test]
+    fn namespaces_fold_down_to_their_last_statement() {
+        let text = "\
+namespace Main(8);
+col fixed STEP(i) { i };
+col witness x;
+x' = x
+    + STEP;
+namespace Other(8);
+col witness y;
+y = 1;
+";
+        assert_eq!(
+            regions_in("file:///test.pil", text),
+            vec![(0, 4), (3, 4), (5, 7)]
+        );
+    }
+}
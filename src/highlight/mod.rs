@@ -0,0 +1,155 @@
+use crate::parser::{convert_position, position_to_offset};
+use crate::symbol::{SemanticIndex, SymbolKind, UseKind};
+use tower_lsp::lsp_types::*;
+
+pub struct DocumentHighlightProvider<'a> {
+    text: &'a str,
+    semantic_index: &'a SemanticIndex,
+}
+
+impl<'a> DocumentHighlightProvider<'a> {
+    pub fn new(text: &'a str, semantic_index: &'a SemanticIndex) -> Self {
+        Self {
+            text,
+            semantic_index,
+        }
+    }
+
+    pub fn get_highlights(&self, position: Position) -> Option<Vec<DocumentHighlight>> {
+        let offset = position_to_offset(position, self.text)?;
+        let id = self.semantic_index.find_symbol_id_at_position(offset)?;
+        let symbol = &self.semantic_index.symbols[&id];
+
+        let mut spans: Vec<_> = self
+            .semantic_index
+            .occurrences_of(id)
+            .map(|occurrence| occurrence.span.clone())
+            .collect();
+        // Machines are indexed under both their full and short name at the same span.
+        spans.sort_by_key(|span| span.start);
+        spans.dedup();
+
+        let highlights = spans
+            .into_iter()
+            .map(|span| {
+                let kind = match symbol.kind {
+                    SymbolKind::Machine
+                    | SymbolKind::Instruction
+                    | SymbolKind::Operation
+                    | SymbolKind::Function
+                    | SymbolKind::Submachine
+                    | SymbolKind::Module
+                    | SymbolKind::Import
+                    | SymbolKind::Link
+                    | SymbolKind::Label
+                    | SymbolKind::Enum
+                    | SymbolKind::EnumVariant
+                    | SymbolKind::Struct
+                    | SymbolKind::Trait
+                    | SymbolKind::Namespace
+                    | SymbolKind::TraitImpl => DocumentHighlightKind::TEXT,
+                    _ => match self.semantic_index.use_kind_at(&span) {
+                        Some(UseKind::Write) => DocumentHighlightKind::WRITE,
+                        Some(UseKind::Read) => DocumentHighlightKind::READ,
+                        Some(UseKind::Declaration) => DocumentHighlightKind::TEXT,
+                        // Register declarations are neither reads nor writes, while every
+                        // PIL occurrence outside its declaration is used by a constraint.
+                        None if symbol.kind == SymbolKind::Register => DocumentHighlightKind::TEXT,
+                        None => DocumentHighlightKind::READ,
+                    },
+                };
+
+                DocumentHighlight {
+                    range: Range::new(
+                        convert_position(span.start, self.text),
+                        convert_position(span.end, self.text),
+                    ),
+                    kind: Some(kind),
+                }
+            })
+            .collect();
+
+        Some(highlights)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::build_semantic_index;
+    use powdr_number::GoldilocksField;
+
+    /// The line and kind of each highlight of the symbol at `needle`.
+    fn highlights(uri: &str, text: &str, needle: &str) -> Vec<(u32, DocumentHighlightKind)> {
+        let uri = Url::parse(uri).unwrap();
+        let result = crate::parser::parse::<GoldilocksField>(text, &uri);
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        let index = build_semantic_index(&result.analyzed, result.parsed.as_ref(), text);
+
+        let offset = text.find(needle).unwrap();
+        let position = convert_position(offset, text);
+        DocumentHighlightProvider::new(text, &index)
+            .get_highlights(position)
+            .unwrap()
+            .into_iter()
+            .map(|highlight| (highlight.range.start.line, highlight.kind.unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn register_writes_and_reads_stay_in_their_machine() {
+        let text = "machine Main with degree: 8 {
+    reg pc[@pc];
+    reg X[<=];
+    reg A;
+
+    instr incr X { A' = A + X }
+
+    function main {
+        A <=X= 1;
+        incr A;
+        return;
+    }
+}
+
+machine Other with degree: 8 {
+    reg pc[@pc];
+    reg X[<=];
+    reg A;
+
+    function main {
+        A <=X= 2;
+        return;
+    }
+}
+";
+        use DocumentHighlightKind as Kind;
+        assert_eq!(
+            highlights("file:///test.asm", text, "A <=X= 1"),
+            vec![
+                (3, Kind::TEXT),
+                (5, Kind::WRITE),
+                (5, Kind::READ),
+                (8, Kind::WRITE),
+                (9, Kind::READ),
+            ]
+        );
+    }
+
+    #[test]
+    fn next_row_identities_write_their_column() {
+        let text = "namespace Main(8);
+col witness x, y;
+x' = x + y;
+y = 1;
+namespace Other(8);
+col witness x;
+x' = x;
+";
+        use DocumentHighlightKind as Kind;
+        assert_eq!(
+            highlights("file:///test.pil", text, "x' = x + y"),
+            vec![(1, Kind::TEXT), (2, Kind::WRITE), (2, Kind::READ)]
+        );
+    }
+}
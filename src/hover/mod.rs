@@ -1,121 +1,175 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
+use crate::budget::with_time_budget;
+use crate::eval::evaluate_constant;
+use crate::literal;
+use crate::panic::catch_panic;
 use crate::parser::AnalyzedDoc;
+use crate::queries::ConstantValues;
 use crate::symbol::{Symbol, SymbolDetails, SymbolKind};
 use powdr_ast::{
-    analyzed::Analyzed, asm_analysis::AnalysisASMFile, parsed::asm::parse_absolute_path,
+    analyzed::{Analyzed, FunctionValueDefinition},
+    asm_analysis::{AnalysisASMFile, Item},
+    parsed::asm::parse_absolute_path,
 };
+use powdr_number::{BigInt, FieldElement, LargeInt};
+use powdr_pil_analyzer::evaluator::{self, Value};
 use tower_lsp::lsp_types::*;
+use tracing::{debug, trace};
 
-pub struct HoverProvider<T> {
-    text: String,
-    analyzed: AnalyzedDoc<T>,
-    semantic_index: crate::symbol::SemanticIndex,
+pub struct HoverProvider<'a, T> {
+    text: &'a str,
+    analyzed: &'a Arc<AnalyzedDoc<T>>,
+    semantic_index: &'a crate::symbol::SemanticIndex,
+    constants: &'a ConstantValues,
 }
 
-impl<T> HoverProvider<T> {
+impl<'a, T: FieldElement> HoverProvider<'a, T> {
     pub fn new(
-        text: String,
-        analyzed: AnalyzedDoc<T>,
-        semantic_index: crate::symbol::SemanticIndex,
+        text: &'a str,
+        analyzed: &'a Arc<AnalyzedDoc<T>>,
+        semantic_index: &'a crate::symbol::SemanticIndex,
+        constants: &'a ConstantValues,
     ) -> Self {
         Self {
             text,
             analyzed,
             semantic_index,
+            constants,
         }
     }
 
-    pub fn get_hover(&self, position: Position) -> (Option<Hover>, Vec<String>) {
-        let mut log_messages = Vec::new();
-
+    pub fn get_hover(&self, position: Position) -> Option<Hover> {
         let offset = match self.position_to_offset(position) {
             Some(off) => {
-                let context = self
-                    .text
-                    .get(off.saturating_sub(10)..off.saturating_add(10))
-                    .unwrap_or("")
-                    .to_string();
-                log_messages.push(format!(
-                    "Converting position Line:{}, Char:{} to offset {}. Text context: '{}'",
-                    position.line, position.character, off, context
-                ));
-
-                //log_messages.push(format!(
-                //    "Available symbol ranges: {:?}",
-                //    self.semantic_index.get_all_ranges()
-                //));
-
+                trace!(
+                    line = position.line,
+                    character = position.character,
+                    offset = off,
+                    context = self
+                        .text
+                        .get(off.saturating_sub(10)..off.saturating_add(10))
+                        .unwrap_or(""),
+                    "converted position to offset"
+                );
                 off
             }
             None => {
-                log_messages.push("Failed to convert position to offset".to_string());
-                return (None, log_messages);
+                debug!(?position, "failed to convert position to offset");
+                return None;
             }
         };
 
-        let symbol = match self.semantic_index.find_symbol_at_position(offset) {
-            Some(sym) => {
-                log_messages.push(format!("Found symbol at offset {}: {:?}", offset, sym));
-                sym
-            }
+        let id = match self.semantic_index.find_symbol_id_at_position(offset) {
+            Some(id) => id,
             None => {
-                log_messages.push(format!("No symbol found at offset {}", offset));
-                return (None, log_messages);
+                debug!(offset, "no symbol found");
+                return self.get_literal_hover(offset);
             }
         };
+        let symbol = &self.semantic_index.symbols[&id];
+        trace!(offset, ?symbol, "found symbol");
 
-        let content = self.get_hover_content(symbol);
-        log_messages.push(format!(
-            "Generated hover content: {} for symbol {:?}",
-            content, symbol
-        ));
+        let mut content = self.get_hover_content(symbol);
+        if let Some(doc) = self.semantic_index.doc(id) {
+            content.push_str(&format!("\n---\n\n{}\n", doc));
+        }
+        trace!(%content, "generated hover content");
 
-        let hover = Some(Hover {
+        Some(Hover {
             contents: HoverContents::Markup(MarkupContent {
                 kind: MarkupKind::Markdown,
                 value: content,
             }),
             range: Some(Range::new(position, position)),
-        });
+        })
+    }
+
+    /// Shows the value of the constant expression or numeric literal at `offset`.
+    fn get_literal_hover(&self, offset: usize) -> Option<Hover> {
+        let literal = literal::literal_at(self.text, offset)?;
+        let span = literal::constant_at(self.text, offset)?;
+        let title = if span == literal {
+            "Literal"
+        } else {
+            "Constant Expression"
+        };
+        let text = &self.text[span.clone()];
+        let value = literal::evaluate(text)?;
+        trace!(text, %value, "evaluated literal");
 
-        (hover, log_messages)
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: format!(
+                    "### {}\n\n\
+                    `{}`\n\n\
+                    {}\n",
+                    title,
+                    text,
+                    literal::describe::<T>(&value)
+                ),
+            }),
+            range: Some(Range::new(
+                crate::parser::convert_position(span.start, self.text),
+                crate::parser::convert_position(span.end, self.text),
+            )),
+        })
     }
 
     fn position_to_offset(&self, position: Position) -> Option<usize> {
-        let lines: Vec<&str> = self.text.lines().collect();
-        let line = lines.get(position.line as usize)?;
-
-        let mut offset = self
-            .text
-            .lines()
-            .take(position.line as usize)
-            .map(|line| line.len() + 1)
-            .sum::<usize>();
-
-        offset += position.character as usize;
-        Some(offset)
+        crate::parser::position_to_offset(position, self.text)
     }
 
     fn get_hover_content(&self, symbol: &Symbol) -> String {
         match (&symbol.kind, &symbol.details) {
-            (SymbolKind::Machine, SymbolDetails::Machine { degree }) => {
+            (SymbolKind::Machine, SymbolDetails::Machine { degree, summary }) => {
                 let degree_text = match degree {
-                    Some(info) => match (info.min, info.max) {
-                        (Some(min), Some(max)) if min == max => format!("Degree: {}", min),
-                        (Some(min), Some(max)) => format!("Degree: Min:{}, Max:{}", min, max),
-                        (Some(val), None) | (None, Some(val)) => format!("Degree: {}", val),
+                    Some(info) => match (&info.min, &info.max) {
+                        (Some(min), Some(max)) if min == max => format!("Degree: {}\n", min),
+                        (Some(min), Some(max)) => format!("Degree: Min:{}, Max:{}\n", min, max),
+                        (Some(val), None) | (None, Some(val)) => format!("Degree: {}\n", val),
                         (None, None) => String::new(),
                     },
                     None => String::new(),
                 };
 
-                format!(
+                let mut content = format!(
                     "### Machine\n\n\
-                    Name: {}\n\
-                    {}\n",
+                    Name: {}\n\n\
+                    {}",
                     symbol.name, degree_text
-                )
+                );
+                if !summary.params.is_empty() {
+                    content.push_str(&format!("\nParameters: `{}`\n", summary.params));
+                }
+                if let Some(latch) = &summary.latch {
+                    content.push_str(&format!("\nLatch: `{}`\n", latch));
+                }
+                if let Some(operation_id) = &summary.operation_id {
+                    content.push_str(&format!("\nOperation id: `{}`\n", operation_id));
+                }
+                if let Some(call_selectors) = &summary.call_selectors {
+                    content.push_str(&format!("\nCall selectors: `{}`\n", call_selectors));
+                }
+                content.push_str(&format!(
+                    "\nRegisters: {}, Instructions: {}, Operations: {}\n",
+                    summary.registers, summary.instructions, summary.operations
+                ));
+                if !summary.submachines.is_empty() {
+                    content.push_str("\nSubmachines:\n");
+                    for (instance, machine_type) in &summary.submachines {
+                        content.push_str(&format!("- `{}`: {}\n", instance, machine_type));
+                    }
+                }
+                if !summary.links.is_empty() {
+                    content.push_str("\nLinks:\n");
+                    for target in &summary.links {
+                        content.push_str(&format!("- `{}`\n", target));
+                    }
+                }
+                content
             }
             (SymbolKind::Register, SymbolDetails::Register { type_info }) => {
                 if type_info.is_empty() {
@@ -133,15 +187,142 @@ impl<T> HoverProvider<T> {
                     )
                 }
             }
-            (SymbolKind::Callable, SymbolDetails::Callable { inputs, outputs }) => {
+            (
+                kind @ (SymbolKind::Instruction | SymbolKind::Operation | SymbolKind::Function),
+                SymbolDetails::Callable { inputs, outputs },
+            ) => {
+                let title = match kind {
+                    SymbolKind::Instruction => "Instruction",
+                    SymbolKind::Operation => "Operation",
+                    _ => "Function",
+                };
                 format!(
-                    "### Instruction\n\n\
+                    "### {}\n\n\
                     Name: {}\n\n\
                     Inputs: {}\n\n\
                     Outputs: {}\n",
-                    symbol.name, inputs, outputs
+                    title, symbol.name, inputs, outputs
+                )
+            }
+            (
+                SymbolKind::Submachine,
+                SymbolDetails::Submachine {
+                    machine_type,
+                    arguments,
+                },
+            ) => {
+                format!(
+                    "### Submachine\n\n\
+                    Name: {}\n\n\
+                    Machine: {}\n\n\
+                    Arguments: {}\n",
+                    symbol.name, machine_type, arguments
+                )
+            }
+            (SymbolKind::Module, SymbolDetails::Module { path }) => {
+                format!(
+                    "### Module\n\n\
+                    Path: {}\n",
+                    path
+                )
+            }
+            (SymbolKind::Import, SymbolDetails::Import { target }) => {
+                format!(
+                    "### Import\n\n\
+                    Name: {}\n\n\
+                    Target: {}\n",
+                    symbol.name, target
+                )
+            }
+            (SymbolKind::Constant, SymbolDetails::Constant { type_info, value }) => {
+                let mut content = format!(
+                    "### Constant\n\n\
+                    Name: {}\n",
+                    symbol.name
+                );
+                if let Some(type_info) = type_info {
+                    content.push_str(&format!("\nType: {}\n", type_info));
+                }
+                if let Some(value) = value {
+                    content.push_str(&format!("\nValue: `{}`\n", value));
+                    content.push_str(&evaluated::<T>(
+                        value,
+                        self.constant(&symbol.name, Self::asm_constant),
+                    ));
+                }
+                content
+            }
+            (kind, SymbolDetails::Column { declaration }) => {
+                let title = match kind {
+                    SymbolKind::FixedColumn => "Fixed Column",
+                    _ => "Witness Column",
+                };
+                format!(
+                    "### {}\n\n\
+                    Name: {}\n\n\
+                    ```\n{}\n```\n",
+                    title, symbol.name, declaration
                 )
             }
+            (
+                SymbolKind::Link,
+                SymbolDetails::Link {
+                    target,
+                    permutation,
+                },
+            ) => {
+                format!(
+                    "### Link\n\n\
+                    Target: {}\n\n\
+                    Kind: {}\n",
+                    target,
+                    if *permutation {
+                        "permutation"
+                    } else {
+                        "lookup"
+                    }
+                )
+            }
+            (SymbolKind::Label, SymbolDetails::Label { function }) => {
+                format!(
+                    "### Label\n\n\
+                    Name: {}\n\n\
+                    Function: {}\n",
+                    symbol.name, function
+                )
+            }
+            (
+                kind,
+                SymbolDetails::Declaration {
+                    declaration, value, ..
+                },
+            ) => {
+                let title = match kind {
+                    SymbolKind::WitnessColumn => "Witness Column",
+                    SymbolKind::FixedColumn => "Fixed Column",
+                    SymbolKind::Intermediate => "Intermediate",
+                    SymbolKind::Constant => "Constant",
+                    SymbolKind::Function => "Function",
+                    SymbolKind::Enum => "Enum",
+                    SymbolKind::EnumVariant => "Enum Variant",
+                    SymbolKind::Struct => "Struct",
+                    SymbolKind::Trait => "Trait",
+                    SymbolKind::Namespace => "Namespace",
+                    _ => "Definition",
+                };
+                let mut content = format!(
+                    "### {}\n\n\
+                    ```\n{}\n```\n",
+                    title, declaration
+                );
+                if let Some(value) = value {
+                    content.push_str(&evaluated::<T>(
+                        value,
+                        self.constant(&symbol.name, Self::pil_constant),
+                    ));
+                }
+                content
+            }
             (SymbolKind::Definition, SymbolDetails::Definition) => {
                 format!(
                     "### Definition\n\n\
@@ -173,4 +354,91 @@ impl<T> HoverProvider<T> {
             _ => format!("### Symbol\n\nName: {}\n", symbol.name),
         }
     }
+
+    /// The value of a constant, evaluated once per analysis.
+    fn constant(&self, name: &str, evaluate: fn(&Self, &str) -> Option<BigInt>) -> Option<BigInt> {
+        if let Some(value) = self.constants.lock().unwrap().get(name) {
+            return value.clone();
+        }
+        let value = evaluate(self, name);
+        self.constants
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), value.clone());
+        value
+    }
+
+    /// The value of a constant of an asm module, evaluated on its definition and
+    /// those of the other constants of the program.
+    fn asm_constant(&self, name: &str) -> Option<BigInt> {
+        let AnalyzedDoc::ASM(asm) = self.analyzed.as_ref() else {
+            return None;
+        };
+        let definitions: Vec<_> = asm
+            .items
+            .iter()
+            .filter_map(|(path, item)| match item {
+                Item::Expression(typed) => Some((path.to_string(), &typed.e)),
+                _ => None,
+            })
+            .collect();
+        // Constants are indexed under their name in the declaring module.
+        let mut paths = definitions
+            .iter()
+            .map(|(path, _)| path)
+            .filter(|path| path.rsplit("::").next() == Some(name));
+        let path = paths.next()?.clone();
+        if paths.next().is_some() {
+            return None;
+        }
+        evaluate_constant(definitions, &path)
+    }
+
+    /// The value of a PIL constant, evaluated by powdr on the analyzed
+    /// definition within the time budget.
+    fn pil_constant(&self, name: &str) -> Option<BigInt> {
+        if !matches!(self.analyzed.as_ref(), AnalyzedDoc::PIL(_)) {
+            return None;
+        }
+        let analyzed = self.analyzed.clone();
+        let name = name.to_string();
+        let key = format!("hover {name}");
+        let value = with_time_budget(&key, move || {
+            catch_panic(|| {
+                let AnalyzedDoc::PIL(pil) = analyzed.as_ref() else {
+                    return None;
+                };
+                let (_, Some(FunctionValueDefinition::Expression(value))) =
+                    pil.definitions.get(&name)?
+                else {
+                    return None;
+                };
+                match evaluator::evaluate_expression::<T>(&value.e, &pil.definitions).ok()? {
+                    Value::Integer(value) => Some(value),
+                    Value::FieldElement(value) => Some(BigInt::from(value.to_arbitrary_integer())),
+                    _ => None,
+                }
+            })
+        });
+        match value {
+            Ok(Ok(value)) => value,
+            Ok(Err(message)) | Err(message) => {
+                debug!(%message, "cannot evaluate constant");
+                None
+            }
+        }
+    }
+}
+
+/// The value of a constant below its definition, `value`, unless they read the same.
+fn evaluated<T: FieldElement>(definition: &str, value: Option<BigInt>) -> String {
+    match value {
+        Some(result) if result.to_string() != definition.trim() => format!(
+            "\nEvaluated: `{}`\n\n{}\n",
+            result,
+            literal::describe::<T>(&result)
+        ),
+        Some(result) => format!("\n{}\n", literal::describe::<T>(&result)),
+        None => String::new(),
+    }
 }
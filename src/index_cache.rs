@@ -0,0 +1,276 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use powdr_ast::asm_analysis::{CallableSymbol, Item, Machine};
+use powdr_ast::parsed::SourceReference;
+use powdr_parser_util::SourceRef;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::parser::AnalyzedDoc;
+use crate::symbol::{SemanticIndex, Symbol, SymbolId, SymbolUse};
+
+/// Entries written by other server versions are ignored.
+const SERVER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Version of the entry layout and of what the indexer puts into it. Bump it
+/// whenever either changes, entries of other schemas are ignored.
+const CACHE_SCHEMA: u32 = 5;
+
+/// Persists the semantic index of workspace files between sessions, so that
+/// startup only re-analyzes files whose content, or the content of one of
+/// their imports, changed.
+#[derive(Debug, Clone)]
+pub struct IndexCache {
+    dir: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    server_version: String,
+    schema: u32,
+    path: PathBuf,
+    hash: String,
+    /// Other files whose content went into the analysis, with their hashes.
+    imports: Vec<(PathBuf, String)>,
+    /// Sorted by id, so that re-adding them restores the ids.
+    symbols: Vec<Symbol>,
+    uses: Vec<SymbolUse>,
+    declarations: HashMap<SymbolId, SymbolId>,
+    docs: HashMap<SymbolId, String>,
+}
+
+impl IndexCache {
+    /// Opens the cache below the user's cache directory, or `$POWDR_LSP_CACHE_DIR`.
+    pub fn open() -> Option<Self> {
+        let root = std::env::var_os("POWDR_LSP_CACHE_DIR")
+            .map(PathBuf::from)
+            .or_else(|| dirs::cache_dir().map(|dir| dir.join("powdr-lsp")))?;
+        Self::open_at(root.join(format!("{SERVER_VERSION}-{CACHE_SCHEMA}"))).ok()
+    }
+
+    pub fn open_at(dir: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    /// Returns the cached index of `path` if it was built from `text` and none of
+    /// its imports changed since.
+    pub fn load(&self, path: &Path, text: &str) -> Option<SemanticIndex> {
+        let content = fs::read_to_string(self.entry_path(path)).ok()?;
+        let entry: CacheEntry = serde_json::from_str(&content).ok()?;
+
+        let fresh = entry.server_version == SERVER_VERSION
+            && entry.schema == CACHE_SCHEMA
+            && entry.path == path
+            && entry.hash == content_hash(text)
+            && entry.imports.iter().all(|(import, hash)| {
+                fs::read_to_string(import).is_ok_and(|text| content_hash(&text) == *hash)
+            });
+        if !fresh {
+            debug!(path = %path.display(), "index cache entry is stale");
+            return None;
+        }
+
+        let mut index = SemanticIndex::new();
+        for symbol in entry.symbols {
+            index.add_symbol(symbol);
+        }
+        for symbol_use in entry.uses {
+            index.add_use(symbol_use);
+        }
+        index.declarations = entry.declarations;
+        for (id, doc) in entry.docs {
+            index.add_doc(id, doc);
+        }
+        Some(index)
+    }
+
+    pub fn store<T>(
+        &self,
+        path: &Path,
+        text: &str,
+        analyzed: &AnalyzedDoc<T>,
+        index: &SemanticIndex,
+    ) {
+        let mut symbols: Vec<_> = index.symbols.iter().collect();
+        symbols.sort_by_key(|(id, _)| **id);
+
+        let imports = imported_files(analyzed)
+            .into_iter()
+            .filter(|import| import != path)
+            .filter_map(|import| {
+                let hash = content_hash(&fs::read_to_string(&import).ok()?);
+                Some((import, hash))
+            })
+            .collect();
+
+        let entry = CacheEntry {
+            server_version: SERVER_VERSION.to_string(),
+            schema: CACHE_SCHEMA,
+            path: path.to_path_buf(),
+            hash: content_hash(text),
+            imports,
+            symbols: symbols
+                .into_iter()
+                .map(|(_, symbol)| symbol.clone())
+                .collect(),
+            uses: index.uses.clone(),
+            declarations: index.declarations.clone(),
+            docs: index.docs.clone(),
+        };
+
+        let result = serde_json::to_string(&entry)
+            .map_err(io::Error::from)
+            .and_then(|content| fs::write(self.entry_path(path), content));
+        if let Err(e) = result {
+            warn!(path = %path.display(), error = %e, "cannot write index cache entry");
+        }
+    }
+
+    fn entry_path(&self, path: &Path) -> PathBuf {
+        let key = content_hash(&path.to_string_lossy());
+        self.dir.join(format!("{key}.json"))
+    }
+}
+
+pub fn content_hash(text: &str) -> String {
+    blake3::hash(text.as_bytes()).to_hex().to_string()
+}
+
+/// Files the importer pulled into the analysis of an asm document, i.e. the
+/// files any part of the analyzed program comes from.
+fn imported_files<T>(analyzed: &AnalyzedDoc<T>) -> BTreeSet<PathBuf> {
+    let AnalyzedDoc::ASM(asm) = analyzed else {
+        return BTreeSet::new();
+    };
+
+    let mut sources = Vec::new();
+    for item in asm.items.values() {
+        match item {
+            Item::Machine(machine) => machine_sources(machine, &mut sources),
+            Item::Expression(expression) => sources.push(expression.e.source_reference()),
+            Item::TraitImplementation(implementation) => sources.push(&implementation.source_ref),
+            // Type and trait declarations carry no source reference.
+            _ => {}
+        }
+    }
+
+    sources
+        .into_iter()
+        .filter_map(|source| source.file_name.as_deref().map(PathBuf::from))
+        .collect()
+}
+
+fn machine_sources<'a>(machine: &'a Machine, sources: &mut Vec<&'a SourceRef>) {
+    sources.extend(machine.registers.iter().map(|register| &register.source));
+    sources.extend(machine.instructions.iter().map(|instr| &instr.source));
+    sources.extend(machine.links.iter().map(|link| &link.source));
+    sources.extend(
+        machine
+            .callable
+            .iter()
+            .map(|callable| match callable.symbol {
+                CallableSymbol::Function(function) => &function.source,
+                CallableSymbol::Operation(operation) => &operation.source,
+            }),
+    );
+    sources.extend(
+        machine
+            .pil
+            .iter()
+            .map(|statement| statement.source_reference()),
+    );
+    sources.extend(
+        machine
+            .submachines
+            .iter()
+            .flat_map(|submachine| &submachine.args)
+            .map(|arg| arg.source_reference()),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbol::{SymbolDetails, SymbolKind};
+    use powdr_number::GoldilocksField;
+
+    fn cache(name: &str) -> (IndexCache, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("powdr-lsp-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache = IndexCache::open_at(dir.join("cache")).unwrap();
+        (cache, dir.join("main.pil"))
+    }
+
+    fn index() -> SemanticIndex {
+        let mut index = SemanticIndex::new();
+        let x = Symbol {
+            kind: SymbolKind::WitnessColumn,
+            span: 28..29,
+            name: "x".to_string(),
+            details: SymbolDetails::Column {
+                declaration: "col witness x;".to_string(),
+            },
+        };
+        let declaration = index.add_symbol(x.clone());
+        index.add_occurrence(Symbol { span: 31..32, ..x }, declaration);
+        index.add_doc(declaration, "The input.".to_string());
+        index
+    }
+
+    fn store(cache: &IndexCache, path: &Path, text: &str) {
+        let analyzed = AnalyzedDoc::<GoldilocksField>::ASM(Default::default());
+        cache.store(path, text, &analyzed, &index());
+    }
+
+    #[test]
+    fn entry_is_reused_for_unchanged_text() {
+        let (cache, path) = cache("unchanged");
+        store(&cache, &path, "namespace main; col witness x;");
+
+        let loaded = cache.load(&path, "namespace main; col witness x;").unwrap();
+        assert_eq!(loaded.symbols.len(), 2);
+    }
+
+    #[test]
+    fn uses_keep_the_doc_of_their_declaration() {
+        let (cache, path) = cache("docs");
+        let text = "namespace main; col witness x; x = 1;";
+        store(&cache, &path, text);
+
+        let loaded = cache.load(&path, text).unwrap();
+        let at_use = loaded.find_symbol_id_at_position(31).unwrap();
+        assert_eq!(loaded.doc(at_use), Some("The input."));
+        assert_eq!(loaded, index());
+    }
+
+    #[test]
+    fn entry_is_stale_after_an_edit() {
+        let (cache, path) = cache("edited");
+        store(&cache, &path, "namespace main; col witness x;");
+
+        assert!(
+            cache
+                .load(&path, "namespace main; col witness y;")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn entry_of_another_schema_is_ignored() {
+        let (cache, path) = cache("schema");
+        let text = "namespace main; col witness x;";
+        store(&cache, &path, text);
+
+        let entry_path = cache.entry_path(&path);
+        let mut entry: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&entry_path).unwrap()).unwrap();
+        entry["schema"] = serde_json::json!(CACHE_SCHEMA - 1);
+        fs::write(&entry_path, entry.to_string()).unwrap();
+
+        assert!(cache.load(&path, text).is_none());
+    }
+}
@@ -1,11 +1,51 @@
 pub mod analyzer;
+pub mod budget;
+pub mod call_hierarchy;
+pub mod check;
+pub mod code_lens;
+pub mod compile;
+pub mod completion;
+pub mod dump;
+pub mod eval;
+pub mod folding;
+pub mod highlight;
 pub mod hover;
+pub mod index_cache;
+pub mod lints;
+pub mod literal;
+pub mod logging;
+pub mod machine_tree;
+pub mod panic;
 pub mod parser;
+pub mod pil_document;
+pub mod progress;
+pub mod project;
+pub mod queries;
+pub mod scheduler;
+pub mod signature_help;
+pub mod soundness;
 pub mod span;
 pub mod symbol;
+pub mod transport;
+pub mod workspace;
+pub mod workspace_symbols;
 
-pub use analyzer::build_semantic_index;
+pub use analyzer::{MachineUnits, build_semantic_index, build_semantic_index_reusing};
+pub use call_hierarchy::{CallGraph, CallHierarchyProvider, build_call_graph};
+pub use code_lens::CodeLensProvider;
+pub use compile::{CompiledPil, Lowered, MachineStats, compile_to_pil, lower, machine_stats};
+pub use completion::CompletionProvider;
+pub use folding::FoldingRangeProvider;
+pub use highlight::DocumentHighlightProvider;
 pub use hover::HoverProvider;
-pub use parser::{AnalyzedDoc, ParseResult, parse};
+pub use machine_tree::{MachineNode, MachineTreeParams, build_machine_tree};
+pub use parser::{AnalyzedDoc, ParseResult, ParsedAst, parse};
+pub use pil_document::PilDocument;
+pub use project::{ParsedDocument, ProjectCache};
+pub use queries::{Lowering, Published, QueryDatabase};
+pub use scheduler::{AnalysisScheduler, Cancellation};
+pub use signature_help::SignatureHelpProvider;
 pub use span::Span;
-pub use symbol::{SemanticIndex, Symbol, SymbolDetails, SymbolId, SymbolKind};
+pub use symbol::{SemanticIndex, Symbol, SymbolDetails, SymbolId, SymbolKind, SymbolUse, UseKind};
+pub use workspace::{FolderConfig, WorkspaceFolder};
+pub use workspace_symbols::{DocumentMatches, WorkspaceSymbolProvider, symbol_information};
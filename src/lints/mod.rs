@@ -0,0 +1,615 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use powdr_ast::analyzed::{
+    AlgebraicExpression, Analyzed, PolynomialType, SymbolKind as PilSymbolKind,
+};
+use powdr_ast::asm_analysis::{CallableSymbol, FunctionStatement, Item, Machine};
+use powdr_ast::parsed::types::Type;
+use powdr_ast::parsed::visitor::{AllChildren, Children};
+use powdr_ast::parsed::{
+    BinaryOperation, BinaryOperator, Expression, IndexAccess, PILFile, PilStatement,
+};
+use powdr_number::{BigInt, FieldElement, LargeInt};
+use serde_json::json;
+use tower_lsp::lsp_types::*;
+
+use crate::analyzer::is_identifier_char;
+use crate::literal;
+use crate::parser::{AnalyzedDoc, convert_position};
+use crate::soundness::{array_name, underconstrained_columns};
+use crate::span::Span;
+use crate::symbol::{SemanticIndex, SymbolKind, UseKind};
+
+pub const LITERAL_OVERFLOW: &str = "literal-overflow";
+pub const ADDRESS_OVERFLOW: &str = "address-overflow";
+pub const UNUSED_SYMBOL: &str = "unused-symbol";
+
+/// Diagnostics found in a document, on top of powdr's own errors. The
+/// soundness check of asm documents needs their lowered PIL and runs apart.
+pub fn lint<T: FieldElement>(
+    text: &str,
+    analyzed: &AnalyzedDoc<T>,
+    index: &SemanticIndex,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = literal_overflows(text, analyzed);
+    diagnostics.extend(unused_symbols(text, analyzed, index));
+    diagnostics.extend(underconstrained_columns(text, analyzed));
+    diagnostics
+}
+
+/// Constants in field element positions that exceed the modulus, and
+/// addresses of the std memory machines that exceed 32 bits. Integers are
+/// unbounded, so only literals typed as field elements can overflow.
+fn literal_overflows<T: FieldElement>(text: &str, analyzed: &AnalyzedDoc<T>) -> Vec<Diagnostic> {
+    let modulus = BigInt::from(T::modulus().to_arbitrary_integer());
+    let address_limit = BigInt::from(u32::MAX);
+    let field_literals = field_literals(text, analyzed);
+    let memory_accesses = memory_accesses(analyzed);
+    let mut diagnostics = Vec::new();
+
+    for span in constants(text) {
+        let source = &text[span.clone()];
+        let Some(value) = literal::evaluate(source) else {
+            continue;
+        };
+        let range = Range::new(
+            convert_position(span.start, text),
+            convert_position(span.end, text),
+        );
+
+        let is_field_element = field_literals.range(span.clone()).next().is_some();
+        if is_field_element && (value >= modulus || -&value >= modulus) {
+            let reduced = ((&value % &modulus) + &modulus) % &modulus;
+            // Rewriting is only equivalent for a single literal, the operators of
+            // a constant expression do not all agree with field arithmetic.
+            let is_literal =
+                literal::parse_literal(source.trim_start_matches('-').trim()).is_some();
+            diagnostics.push(Diagnostic {
+                range,
+                severity: Some(DiagnosticSeverity::WARNING),
+                code: Some(NumberOrString::String(LITERAL_OVERFLOW.to_string())),
+                message: format!(
+                    "`{}` exceeds the field modulus {} and is reduced to {}",
+                    source, modulus, reduced
+                ),
+                source: Some("powdr".to_string()),
+                data: is_literal.then(|| json!({ "replacement": reduced.to_string() })),
+                ..Default::default()
+            });
+        } else if value > address_limit && is_memory_address(text, &span, &memory_accesses) {
+            diagnostics.push(Diagnostic {
+                range,
+                severity: Some(DiagnosticSeverity::WARNING),
+                code: Some(NumberOrString::String(ADDRESS_OVERFLOW.to_string())),
+                message: format!(
+                    "Address `{}` does not fit into the 32 bits of the std memory machines",
+                    source
+                ),
+                source: Some("powdr".to_string()),
+                ..Default::default()
+            });
+        }
+    }
+
+    diagnostics
+}
+
+/// Start offsets of the literals that stand for field elements. In PIL files
+/// these are the literals the type inference typed `fe` or `expr`, in asm
+/// files, where types are not inferred, the literals of constraints, of
+/// function statements and of definitions declared `fe`.
+fn field_literals<T>(text: &str, analyzed: &AnalyzedDoc<T>) -> BTreeSet<usize> {
+    let mut literals = BTreeSet::new();
+    match analyzed {
+        AnalyzedDoc::PIL(pil) => {
+            for (_, value) in pil.definitions.values() {
+                let numbers = value
+                    .iter()
+                    .flat_map(|value| value.children())
+                    .flat_map(|expr| expr.all_children());
+                for expr in numbers {
+                    if let Expression::Number(source, number) = expr {
+                        if matches!(number.type_, Some(Type::Fe | Type::Expr)) {
+                            literals.insert(source.start);
+                        }
+                    }
+                }
+            }
+            // Constraints are not kept as parsed expressions by the analysis.
+            if let Ok(PILFile(statements)) = powdr_parser::parse(None, text) {
+                for statement in &statements {
+                    statement_literals(statement, &mut literals);
+                }
+            }
+        }
+        AnalyzedDoc::ASM(asm) => {
+            for item in asm.items.values() {
+                match item {
+                    Item::Machine(machine) => machine_literals(machine, &mut literals),
+                    Item::Expression(typed)
+                        if typed
+                            .type_scheme
+                            .as_ref()
+                            .is_some_and(|scheme| scheme.ty == Type::Fe) =>
+                    {
+                        expression_literals(&typed.e, &mut literals);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    literals
+}
+
+/// Literals of the constraints of a machine, of its instruction bodies and of
+/// the statements of its functions, whose registers hold field elements.
+fn machine_literals(machine: &Machine, literals: &mut BTreeSet<usize>) {
+    let bodies = machine
+        .instructions
+        .iter()
+        .flat_map(|instr| &instr.instruction.body.0);
+    for statement in machine.pil.iter().chain(bodies) {
+        statement_literals(statement, literals);
+    }
+
+    for callable in &machine.callable {
+        let CallableSymbol::Function(func) = callable.symbol else {
+            continue;
+        };
+        for statement in &func.body.statements {
+            match statement {
+                FunctionStatement::Assignment(assignment) => {
+                    expression_literals(&assignment.rhs, literals)
+                }
+                FunctionStatement::Instruction(instruction) => instruction
+                    .inputs
+                    .iter()
+                    .for_each(|input| expression_literals(input, literals)),
+                FunctionStatement::Return(ret) => ret
+                    .values
+                    .iter()
+                    .for_each(|value| expression_literals(value, literals)),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Field element literals of constraints and of definitions declared `fe`.
+fn statement_literals(statement: &PilStatement, literals: &mut BTreeSet<usize>) {
+    match statement {
+        PilStatement::Expression(_, expr) => expression_literals(expr, literals),
+        PilStatement::LetStatement(_, _, Some(scheme), Some(value)) if scheme.ty == Type::Fe => {
+            expression_literals(value, literals)
+        }
+        _ => {}
+    }
+}
+
+/// Literals of a field element expression. Exponents and indices are
+/// integers, and the arguments of calls have the types of the parameters.
+fn expression_literals(expr: &Expression, literals: &mut BTreeSet<usize>) {
+    match expr {
+        Expression::Number(source, _) => {
+            literals.insert(source.start);
+        }
+        Expression::BinaryOperation(
+            _,
+            BinaryOperation {
+                left,
+                op: BinaryOperator::Pow,
+                ..
+            },
+        ) => expression_literals(left, literals),
+        Expression::IndexAccess(_, IndexAccess { array, .. }) => {
+            expression_literals(array, literals)
+        }
+        Expression::FunctionCall(..) | Expression::LambdaExpression(..) => {}
+        _ => expr
+            .children()
+            .for_each(|child| expression_literals(child, literals)),
+    }
+}
+
+/// Instructions never invoked, registers never read, submachines neither
+/// linked to nor passed to another submachine, imports never referenced and witness columns no constraint mentions.
+fn unused_symbols<T>(
+    text: &str,
+    analyzed: &AnalyzedDoc<T>,
+    index: &SemanticIndex,
+) -> Vec<Diagnostic> {
+    let mut unused: Vec<(Span, String)> = Vec::new();
+
+    match analyzed {
+        AnalyzedDoc::ASM(_) => {
+            // Equal names in different machines are different symbols.
+            let read: HashSet<(&str, &str)> = index
+                .uses
+                .iter()
+                .filter(|symbol_use| symbol_use.kind == UseKind::Read)
+                .map(|symbol_use| (symbol_use.scope.as_str(), symbol_use.name.as_str()))
+                .collect();
+
+            for declaration in &index.uses {
+                if declaration.kind != UseKind::Declaration
+                    || read.contains(&(declaration.scope.as_str(), declaration.name.as_str()))
+                {
+                    continue;
+                }
+                let kind = index.symbols.values().find_map(|symbol| {
+                    (symbol.span == declaration.span && symbol.name == declaration.name)
+                        .then_some(&symbol.kind)
+                });
+                let name = &declaration.name;
+                let message = match kind {
+                    Some(SymbolKind::Instruction) => {
+                        format!("Instruction `{name}` is never invoked")
+                    }
+                    Some(SymbolKind::Register) => format!("Register `{name}` is never read"),
+                    Some(SymbolKind::Submachine) => {
+                        format!("Submachine `{name}` is never linked to or passed to a submachine")
+                    }
+                    Some(SymbolKind::Import) => format!("Import `{name}` is never used"),
+                    _ => continue,
+                };
+                unused.push((declaration.span.clone(), message));
+            }
+        }
+        AnalyzedDoc::PIL(pil) => {
+            let constrained = constrained_columns(pil);
+            for (name, (symbol, _)) in &pil.definitions {
+                if !matches!(symbol.kind, PilSymbolKind::Poly(PolynomialType::Committed))
+                    || constrained.contains(name.as_str())
+                {
+                    continue;
+                }
+                let declaration = index.uses.iter().find(|symbol_use| {
+                    symbol_use.kind == UseKind::Declaration && symbol_use.name == *name
+                });
+                if let Some(declaration) = declaration {
+                    unused.push((
+                        declaration.span.clone(),
+                        format!("Witness column `{}` is not used by any constraint", name),
+                    ));
+                }
+            }
+        }
+    }
+
+    unused.sort_by_key(|(span, _)| span.start);
+    unused.dedup_by_key(|(span, _)| span.clone());
+    unused
+        .into_iter()
+        .map(|(span, message)| Diagnostic {
+            range: Range::new(
+                convert_position(span.start, text),
+                convert_position(span.end, text),
+            ),
+            severity: Some(DiagnosticSeverity::WARNING),
+            code: Some(NumberOrString::String(UNUSED_SYMBOL.to_string())),
+            message,
+            source: Some("powdr".to_string()),
+            tags: Some(vec![DiagnosticTag::UNNECESSARY]),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Columns referenced by identities, directly or through intermediate columns.
+fn constrained_columns<T>(pil: &Analyzed<T>) -> HashSet<&str> {
+    let mut pending: Vec<&str> = pil
+        .identities
+        .iter()
+        .flat_map(|identity| identity.all_children())
+        .filter_map(reference_name)
+        .collect();
+
+    let mut constrained = HashSet::new();
+    while let Some(name) = pending.pop() {
+        if !constrained.insert(name) {
+            continue;
+        }
+        if let Some((_, expressions)) = pil.intermediate_columns.get(name) {
+            pending.extend(
+                expressions
+                    .iter()
+                    .flat_map(|expr| expr.all_children())
+                    .filter_map(reference_name),
+            );
+        }
+    }
+    constrained
+}
+
+fn reference_name<T>(expr: &AlgebraicExpression<T>) -> Option<&str> {
+    match expr {
+        AlgebraicExpression::Reference(reference) => Some(array_name(&reference.name)),
+        _ => None,
+    }
+}
+
+/// Offers to replace overflowing literals by their reduced form.
+pub fn quick_fixes(uri: &Url, diagnostics: &[Diagnostic]) -> Vec<CodeActionOrCommand> {
+    diagnostics
+        .iter()
+        .filter(|d| d.code == Some(NumberOrString::String(LITERAL_OVERFLOW.to_string())))
+        .filter_map(|diagnostic| {
+            let replacement = diagnostic.data.as_ref()?.get("replacement")?.as_str()?;
+            let edit = TextEdit::new(diagnostic.range, replacement.to_string());
+
+            Some(CodeActionOrCommand::CodeAction(CodeAction {
+                title: format!("Replace with reduced value {}", replacement),
+                kind: Some(CodeActionKind::QUICKFIX),
+                diagnostics: Some(vec![diagnostic.clone()]),
+                edit: Some(WorkspaceEdit {
+                    changes: Some(HashMap::from([(uri.clone(), vec![edit])])),
+                    ..Default::default()
+                }),
+                is_preferred: Some(true),
+                ..Default::default()
+            }))
+        })
+        .collect()
+}
+
+/// Spans of the outermost constants in code, comments and strings are skipped.
+fn constants(text: &str) -> Vec<Span> {
+    let mut spans: Vec<Span> = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    let mut previous = ' ';
+
+    while let Some((pos, c)) = chars.next() {
+        let rest = &text[pos..];
+        if rest.starts_with("//") {
+            let end = rest.find('\n').map_or(text.len(), |end| pos + end);
+            while chars.next_if(|(p, _)| *p < end).is_some() {}
+            previous = ' ';
+            continue;
+        }
+        if rest.starts_with("/*") {
+            let end = rest.find("*/").map_or(text.len(), |end| pos + end + 2);
+            while chars.next_if(|(p, _)| *p < end).is_some() {}
+            previous = ' ';
+            continue;
+        }
+        if c == '"' {
+            while let Some((_, c)) = chars.next() {
+                match c {
+                    '\\' => {
+                        chars.next();
+                    }
+                    '"' => break,
+                    _ => {}
+                }
+            }
+            previous = '"';
+            continue;
+        }
+
+        if c.is_ascii_digit() && !is_identifier_char(previous) && previous != '.' {
+            let covered = spans.last().is_some_and(|span| pos < span.end);
+            if let Some(span) = literal::constant_at(text, pos).filter(|_| !covered) {
+                spans.push(span);
+            }
+        }
+        previous = c;
+    }
+
+    spans
+}
+
+/// Operations of the std memory machines the document imports, e.g. `mload`,
+/// `mstore` and `mstore_bootloader`. Their first argument is an address.
+fn memory_accesses<T>(analyzed: &AnalyzedDoc<T>) -> HashSet<&str> {
+    let AnalyzedDoc::ASM(asm) = analyzed else {
+        return HashSet::new();
+    };
+    asm.items
+        .iter()
+        .filter_map(|(path, item)| match item {
+            Item::Machine(machine) if is_std_memory(&path.to_string()) => Some(machine),
+            _ => None,
+        })
+        .flat_map(|machine| &machine.callable)
+        .filter(|callable| matches!(callable.symbol, CallableSymbol::Operation(_)))
+        .map(|callable| callable.name)
+        .collect()
+}
+
+/// Whether `path` is a machine of a memory module of the std, such as
+/// `::std::machines::memory_with_bootloader_write::MemoryWithBootloaderWrite`.
+fn is_std_memory(path: &str) -> bool {
+    path.trim_start_matches("::")
+        .strip_prefix("std::machines::")
+        .is_some_and(|rest| rest.split("::").any(|part| part.starts_with("memory")))
+}
+
+/// Whether the constant is the first argument of a memory access, e.g.
+/// `mstore 0x100, 1;` or `memory.mload(0x100, STEP)`.
+fn is_memory_address(text: &str, span: &Span, memory_accesses: &HashSet<&str>) -> bool {
+    let before = text[..span.start].trim_end();
+    let before = before.strip_suffix('(').unwrap_or(before).trim_end();
+    let start = before
+        .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .map_or(0, |pos| pos + 1);
+    memory_accesses.contains(&before[start..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbol::{Symbol, SymbolDetails, SymbolUse};
+    use powdr_number::GoldilocksField;
+
+    fn register(index: &mut SemanticIndex, scope: &str, span: Span, kind: UseKind) {
+        index.add_use(SymbolUse {
+            name: "A".to_string(),
+            span: span.clone(),
+            kind,
+            scope: scope.to_string(),
+        });
+        if kind == UseKind::Declaration {
+            index.add_symbol(Symbol {
+                kind: SymbolKind::Register,
+                name: "A".to_string(),
+                span,
+                details: SymbolDetails::Register {
+                    type_info: String::new(),
+                },
+            });
+        }
+    }
+
+    #[test]
+    fn reads_only_count_in_their_machine() {
+        let text = "machine Main { reg A; A <=X= A; }\nmachine Other { reg A; }\n";
+        let mut index = SemanticIndex::new();
+        register(&mut index, "::Main", 19..20, UseKind::Declaration);
+        register(&mut index, "::Main", 29..30, UseKind::Read);
+        register(&mut index, "::Other", 54..55, UseKind::Declaration);
+
+        let analyzed = AnalyzedDoc::<GoldilocksField>::ASM(Default::default());
+        let diagnostics = unused_symbols(text, &analyzed, &index);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range.start, Position::new(1, 20));
+        assert_eq!(diagnostics[0].message, "Register `A` is never read");
+    }
+
+    #[test]
+    fn constants_skip_comments_and_strings() {
+        let text = "let x = 5; // 7\nlet s = \"9\"; /* 3 */ let y = 0x10;";
+        let spans: Vec<_> = constants(text)
+            .into_iter()
+            .map(|span| &text[span])
+            .collect();
+        assert_eq!(spans, vec!["5", "0x10"]);
+    }
+
+    #[test]
+    fn first_argument_of_memory_access_is_an_address() {
+        let text = "mstore 0x100, 1;\nmemory.mload(0x200, STEP);\nfoo 0x300;";
+        let span_of = |literal: &str| {
+            let start = text.find(literal).unwrap();
+            start..start + literal.len()
+        };
+        let accesses = HashSet::from(["mload", "mstore"]);
+        assert!(is_memory_address(text, &span_of("0x100"), &accesses));
+        assert!(is_memory_address(text, &span_of("0x200"), &accesses));
+        assert!(!is_memory_address(text, &span_of("0x300"), &accesses));
+    }
+
+    fn overflows(text: &str) -> Vec<Diagnostic> {
+        overflows_in("file:///test.pil", text)
+    }
+
+    fn overflows_in(uri: &str, text: &str) -> Vec<Diagnostic> {
+        let uri = Url::parse(uri).unwrap();
+        let result = crate::parser::parse::<GoldilocksField>(text, &uri);
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        literal_overflows(text, &result.analyzed)
+    }
+
+    #[test]
+    fn only_field_element_literals_overflow() {
+        let text = "namespace Main(8);\n\
+            let n: int = 18446744069414584322;\n\
+            let c: fe = 18446744069414584322;\n\
+            col witness x;\n\
+            x = 18446744069414584322 * x**18446744069414584322;\n";
+        let diagnostics = overflows(text);
+
+        let lines: Vec<_> = diagnostics.iter().map(|d| d.range.start.line).collect();
+        assert_eq!(lines, vec![2, 4]);
+        assert_eq!(diagnostics[1].range.start.character, 4);
+        let uri = Url::parse("file:///test.pil").unwrap();
+        let fixes = quick_fixes(&uri, &diagnostics);
+        assert_eq!(fixes.len(), 2);
+        let CodeActionOrCommand::CodeAction(fix) = &fixes[0] else {
+            unreachable!()
+        };
+        assert_eq!(fix.title, "Replace with reduced value 1");
+    }
+
+    #[test]
+    fn constant_expressions_are_not_rewritten() {
+        let text = "namespace Main(8);\ncol witness x;\nx = 0x10000000000000000 + 1;\n";
+        let diagnostics = overflows(text);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].code,
+            Some(NumberOrString::String(LITERAL_OVERFLOW.to_string()))
+        );
+        let uri = Url::parse("file:///test.pil").unwrap();
+        assert!(quick_fixes(&uri, &diagnostics).is_empty());
+    }
+
+    #[test]
+    fn function_statements_and_instruction_arguments_are_field_elements() {
+        let text = "machine Main with degree: 8 {
+    reg pc[@pc];
+    reg X[<=];
+    reg A;
+
+    instr incr X { A' = A + X + 0x10000000000000000 }
+
+    function main {
+        A <=X= 0x10000000000000000;
+        incr 0x10000000000000000;
+        return;
+    }
+}
+";
+        let diagnostics = overflows_in("file:///test.asm", text);
+
+        let lines: Vec<_> = diagnostics.iter().map(|d| d.range.start.line).collect();
+        assert_eq!(lines, vec![5, 8, 9]);
+        assert!(
+            diagnostics
+                .iter()
+                .all(|d| { d.code == Some(NumberOrString::String(LITERAL_OVERFLOW.to_string())) })
+        );
+    }
+
+    #[test]
+    fn addresses_of_every_std_memory_operation_are_checked() {
+        let text = "use std::machines::memory_with_bootloader_write::MemoryWithBootloaderWrite;
+
+machine Main with degree: 65536 {
+    reg pc[@pc];
+    reg X[<=];
+    reg Y[<=];
+
+    col fixed STEP(i) { i };
+    MemoryWithBootloaderWrite memory;
+
+    instr mstore_bootloader X, Y link ~> memory.mstore_bootloader(X, STEP, Y);
+
+    function main {
+        mstore_bootloader 0x100000000, 1;
+        return;
+    }
+}
+";
+        let diagnostics = overflows_in("file:///test.asm", text);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range.start, Position::new(13, 26));
+        assert_eq!(
+            diagnostics[0].code,
+            Some(NumberOrString::String(ADDRESS_OVERFLOW.to_string()))
+        );
+    }
+
+    #[test]
+    fn memory_modules_of_the_std_are_recognized() {
+        assert!(is_std_memory("::std::machines::memory::Memory"));
+        assert!(is_std_memory(
+            "::std::machines::memory_with_bootloader_write::MemoryWithBootloaderWrite"
+        ));
+        assert!(!is_std_memory("::std::machines::binary::Binary"));
+        assert!(!is_std_memory("::main::memory::Memory"));
+    }
+}
@@ -0,0 +1,335 @@
+use powdr_number::{BigInt, BigUint, FieldElement, LargeInt};
+
+use crate::span::Span;
+
+/// Characters that can appear in a constant expression made of literals.
+const EXPRESSION_CHARS: &str = "0123456789abcdefABCDEFxX_ +-*/%()<>&|^";
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(BigInt),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+/// Larger exponents and shifts are not evaluated, hovering must stay cheap.
+const MAX_EXPONENT: usize = 1024;
+
+const OPERATORS: &[&str] = &["**", "<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^"];
+
+/// Parses a decimal or `0x` prefixed hexadecimal literal.
+pub fn parse_literal(text: &str) -> Option<BigUint> {
+    let (digits, radix) = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => (hex, 16),
+        None => (text, 10),
+    };
+    if digits.is_empty() {
+        return None;
+    }
+
+    let mut value = BigUint::from(0u32);
+    for c in digits.chars().filter(|c| *c != '_') {
+        value = value * BigUint::from(radix) + BigUint::from(c.to_digit(radix)?);
+    }
+    Some(value)
+}
+
+/// Evaluates an expression made only of literals, e.g. `2**8 - 1`.
+pub fn evaluate(text: &str) -> Option<BigInt> {
+    let tokens = tokenize(text)?;
+    let mut parser = Parser { tokens, pos: 0 };
+    let value = parser.binary(0)?;
+    (parser.pos == parser.tokens.len()).then_some(value)
+}
+
+/// Returns the span of the numeric literal at `offset`, including a leading unary minus.
+pub fn literal_at(text: &str, offset: usize) -> Option<Span> {
+    let is_literal_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    if !text.get(offset..)?.starts_with(is_literal_char) {
+        return None;
+    }
+
+    let start = text[..offset]
+        .rfind(|c: char| !is_literal_char(c))
+        .map_or(0, |pos| pos + 1);
+    let end = text[offset..]
+        .find(|c: char| !is_literal_char(c))
+        .map_or(text.len(), |pos| offset + pos);
+    if !text[start..end].starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+
+    // `-1` is a literal, `a -1` is a subtraction.
+    if let Some(before) = text[..start].strip_suffix('-') {
+        let operand_before = before
+            .trim_end()
+            .ends_with(|c: char| is_literal_char(c) || c == ')' || c == ']');
+        if !operand_before {
+            return Some(start - 1..end);
+        }
+    }
+
+    Some(start..end)
+}
+
+/// Returns the span of the largest constant around `offset`: a literal-only
+/// expression, a power of literals, or the literal itself.
+pub fn constant_at(text: &str, offset: usize) -> Option<Span> {
+    let literal = literal_at(text, offset)?;
+    let span = expression_at(text, offset)
+        .or_else(|| power_at(text, &literal))
+        .filter(|span| span.start <= literal.start && literal.end <= span.end)
+        .unwrap_or(literal);
+    Some(span)
+}
+
+/// Returns the span of the literal-only expression on the line around `offset`.
+fn expression_at(text: &str, offset: usize) -> Option<Span> {
+    let is_expression_char = |c: char| EXPRESSION_CHARS.contains(c);
+    let start = text[..offset]
+        .rfind(|c: char| !is_expression_char(c))
+        .map_or(0, |pos| pos + 1);
+    let end = text[offset..]
+        .find(|c: char| !is_expression_char(c))
+        .map_or(text.len(), |pos| offset + pos);
+
+    let mut span = start..end;
+    // Drop surrounding whitespace and parentheses that belong to the enclosing code.
+    loop {
+        let expr = &text[span.clone()];
+        let opens = expr.matches('(').count();
+        let closes = expr.matches(')').count();
+        if expr.starts_with(' ') || (expr.starts_with('(') && opens > closes) {
+            span.start += 1;
+        } else if expr.ends_with(' ') || (expr.ends_with(')') && closes > opens) {
+            span.end -= 1;
+        } else {
+            break;
+        }
+        if span.is_empty() {
+            return None;
+        }
+    }
+
+    // An operator at the edge binds to code outside the window, so the window
+    // is not a subexpression, e.g. the `2 + 3` in `a * 2 + 3`.
+    let tokens = tokenize(&text[span.clone()])?;
+    let dangling = matches!(tokens.first(), Some(Token::Op(op)) if *op != "-")
+        || matches!(tokens.last(), Some(Token::Op(_)));
+    let has_operator = tokens
+        .iter()
+        .skip(1)
+        .any(|token| matches!(token, Token::Op(_)));
+    (has_operator && !dangling).then_some(span)
+}
+
+/// `**` binds tightest, so `base ** exponent` is a subexpression wherever it appears.
+fn power_at(text: &str, literal: &Span) -> Option<Span> {
+    let is_literal_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+
+    let after = &text[literal.end..];
+    let exponent = after.trim_start().strip_prefix("**").map(str::trim_start);
+    if let Some(exponent) = exponent.filter(|e| e.starts_with(|c: char| c.is_ascii_digit())) {
+        let len = exponent
+            .find(|c: char| !is_literal_char(c))
+            .unwrap_or(exponent.len());
+        let end = text.len() - exponent.len() + len;
+        return Some(literal.start..end);
+    }
+
+    let before = text[..literal.start]
+        .trim_end()
+        .strip_suffix("**")?
+        .trim_end();
+    let base_start = before
+        .rfind(|c: char| !is_literal_char(c))
+        .map_or(0, |pos| pos + 1);
+    before[base_start..]
+        .starts_with(|c: char| c.is_ascii_digit())
+        .then_some(base_start..literal.end)
+}
+
+/// Markdown lines with the decimal, hex, binary and field forms of `value`.
+pub fn describe<T: FieldElement>(value: &BigInt) -> String {
+    let modulus = BigInt::from(T::modulus().to_arbitrary_integer());
+    let canonical = ((value % &modulus) + &modulus) % &modulus;
+
+    let mut lines = vec![format!("Decimal: `{}`", value)];
+    if *value >= BigInt::from(0) {
+        lines.push(format!("Hex: `{:#x}`", value));
+        lines.push(format!("Binary: `{:#b}`", value));
+    }
+
+    if canonical == *value {
+        lines.push(format!("Field element: `{}`", canonical));
+    } else if *value < BigInt::from(0) && -value < modulus {
+        lines.push(format!(
+            "Field element: `{}` (≡ `p - {}`)",
+            canonical, -value
+        ));
+    } else {
+        lines.push(format!(
+            "Field element: `{}` (wraps around `p = {}`)",
+            canonical, modulus
+        ));
+    }
+
+    lines.join("\n\n")
+}
+
+fn tokenize(text: &str) -> Option<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+
+    while !rest.is_empty() {
+        if let Some(r) = rest.strip_prefix('(') {
+            tokens.push(Token::Open);
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix(')') {
+            tokens.push(Token::Close);
+            rest = r;
+        } else if rest.starts_with(|c: char| c.is_ascii_digit()) {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Number(BigInt::from(parse_literal(&rest[..end])?)));
+            rest = &rest[end..];
+        } else {
+            let op = OPERATORS.iter().find(|op| rest.starts_with(**op))?;
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        }
+        rest = rest.trim_start();
+    }
+
+    Some(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+/// Binary operators by increasing precedence, `**` is handled separately.
+const PRECEDENCE: &[&[&str]] = &[
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn binary(&mut self, level: usize) -> Option<BigInt> {
+        let Some(operators) = PRECEDENCE.get(level) else {
+            return self.unary();
+        };
+
+        let mut left = self.binary(level + 1)?;
+        while let Some(Token::Op(op)) = self.peek() {
+            let op = *op;
+            if !operators.contains(&op) {
+                break;
+            }
+            self.pos += 1;
+            let right = self.binary(level + 1)?;
+            left = match op {
+                "|" => left | right,
+                "^" => left ^ right,
+                "&" => left & right,
+                "<<" => left << exponent(&right)?,
+                ">>" => left >> exponent(&right)?,
+                "+" => left + right,
+                "-" => left - right,
+                "*" => left * right,
+                "/" if right != BigInt::from(0) => left / right,
+                "%" if right != BigInt::from(0) => left % right,
+                _ => return None,
+            };
+        }
+        Some(left)
+    }
+
+    fn unary(&mut self) -> Option<BigInt> {
+        if self.peek() == Some(&Token::Op("-")) {
+            self.pos += 1;
+            return Some(-self.unary()?);
+        }
+        self.power()
+    }
+
+    fn power(&mut self) -> Option<BigInt> {
+        let base = self.primary()?;
+        if self.peek() == Some(&Token::Op("**")) {
+            self.pos += 1;
+            // Right associative, and binds tighter than a unary minus on its right.
+            let power = self.unary()?;
+            return Some(base.pow(exponent(&power)? as u32));
+        }
+        Some(base)
+    }
+
+    fn primary(&mut self) -> Option<BigInt> {
+        match self.tokens.get(self.pos)?.clone() {
+            Token::Number(value) => {
+                self.pos += 1;
+                Some(value)
+            }
+            Token::Open => {
+                self.pos += 1;
+                let value = self.binary(0)?;
+                (self.peek() == Some(&Token::Close)).then_some(())?;
+                self.pos += 1;
+                Some(value)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn exponent(value: &BigInt) -> Option<usize> {
+    usize::try_from(value)
+        .ok()
+        .filter(|exponent| *exponent <= MAX_EXPONENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operators_bind_by_precedence() {
+        assert_eq!(evaluate("1 + 2 * 3"), Some(BigInt::from(7)));
+        assert_eq!(evaluate("(1 + 2) * 3"), Some(BigInt::from(9)));
+        assert_eq!(evaluate("1 << 2 + 1"), Some(BigInt::from(8)));
+        assert_eq!(evaluate("1 | 2 & 3"), Some(BigInt::from(3)));
+        assert_eq!(evaluate("7 - 2 - 1"), Some(BigInt::from(4)));
+    }
+
+    #[test]
+    fn unary_minus_binds_looser_than_power() {
+        assert_eq!(evaluate("-2**2"), Some(BigInt::from(-4)));
+        assert_eq!(evaluate("2 - -1"), Some(BigInt::from(3)));
+        assert_eq!(evaluate("(-2)**2"), Some(BigInt::from(4)));
+    }
+
+    #[test]
+    fn power_is_right_associative() {
+        assert_eq!(evaluate("2**3**2"), Some(BigInt::from(512)));
+        assert_eq!(evaluate("2**2000"), None);
+    }
+
+    #[test]
+    fn hex_and_separated_literals() {
+        assert_eq!(evaluate("0x10 + 0xff"), Some(BigInt::from(271)));
+        assert_eq!(evaluate("1_000"), Some(BigInt::from(1000)));
+        assert_eq!(evaluate("0x"), None);
+        assert_eq!(evaluate("1 +"), None);
+    }
+}
@@ -0,0 +1,69 @@
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use tracing::error;
+use tracing_subscriber::EnvFilter;
+
+/// Environment variable holding the default log filter.
+pub const LOG_ENV: &str = "POWDR_LSP_LOG";
+
+#[derive(Debug, Default)]
+pub struct LogOptions {
+    /// `EnvFilter` directives, e.g. `info,powdr_lsp::analyzer=trace`.
+    pub filter: Option<String>,
+    pub file: Option<PathBuf>,
+}
+
+/// Removes `--log <filter>` and `--log-file <path>` from the arguments.
+pub fn extract_log_options(args: Vec<String>) -> Result<(LogOptions, Vec<String>), String> {
+    let mut options = LogOptions::default();
+    let mut rest = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--log" => {
+                options.filter = Some(args.next().ok_or("Missing filter after --log")?);
+            }
+            "--log-file" => {
+                options.file = Some(PathBuf::from(
+                    args.next().ok_or("Missing path after --log-file")?,
+                ));
+            }
+            _ => rest.push(arg),
+        }
+    }
+
+    Ok((options, rest))
+}
+
+/// Installs the global subscriber. Logs never go to stdout, which carries the
+/// protocol in stdio mode. Without a filter only warnings and errors are shown.
+pub fn init(options: &LogOptions) -> Result<(), String> {
+    let filter = options
+        .filter
+        .clone()
+        .or_else(|| std::env::var(LOG_ENV).ok())
+        .unwrap_or_else(|| "warn".to_string());
+    let filter = EnvFilter::try_new(&filter).map_err(|e| format!("Invalid log filter: {e}"))?;
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(false);
+
+    match &options.file {
+        Some(path) => {
+            let file = File::create(path)
+                .map_err(|e| format!("Cannot create log file {}: {e}", path.display()))?;
+            builder.with_writer(Mutex::new(file)).init();
+        }
+        None => builder.with_writer(std::io::stderr).init(),
+    }
+
+    // Panics are caught and reported by the analysis, the default hook would
+    // only add an unstructured line to stderr.
+    std::panic::set_hook(Box::new(|info| error!(%info, "panic")));
+
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use powdr_ast::asm_analysis::{AnalysisASMFile, Machine};
+use powdr_ast::parsed::asm::AbsoluteSymbolPath;
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::TextDocumentIdentifier;
+
+pub const MACHINE_TREE_METHOD: &str = "powdr/machineTree";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MachineTreeParams {
+    pub text_document: TextDocumentIdentifier,
+    /// Name of the root machine, `Main` when omitted.
+    pub machine: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MachineNode {
+    /// Name of the submachine instance, `None` for the root machine.
+    pub instance: Option<String>,
+    pub machine: String,
+    pub min_degree: Option<String>,
+    pub max_degree: Option<String>,
+    pub arguments: Vec<String>,
+    /// Operations of this instance that its parent links to.
+    pub operations: Vec<String>,
+    pub children: Vec<MachineNode>,
+}
+
+pub fn build_machine_tree(asm: &AnalysisASMFile, entry: &str) -> Option<MachineNode> {
+    let (path, machine) = asm
+        .machines()
+        .find(|(path, _)| path.relative_to(&AbsoluteSymbolPath::default()).to_string() == entry)?;
+
+    let mut stack = vec![path.to_string()];
+    Some(build_node(
+        asm,
+        None,
+        path.to_string(),
+        machine,
+        vec![],
+        vec![],
+        &mut stack,
+    ))
+}
+
+fn build_node(
+    asm: &AnalysisASMFile,
+    instance: Option<String>,
+    machine_name: String,
+    machine: &Machine,
+    arguments: Vec<String>,
+    operations: Vec<String>,
+    stack: &mut Vec<String>,
+) -> MachineNode {
+    let mut children = Vec::new();
+
+    for submachine in &machine.submachines {
+        let ty = submachine.ty.to_string();
+        // A well-formed program cannot instantiate itself, but an incomplete edit can.
+        if stack.contains(&ty) {
+            continue;
+        }
+        let Some((_, child)) = asm.machines().find(|(path, _)| path.to_string() == ty) else {
+            continue;
+        };
+
+        let mut linked = Vec::new();
+        let links = machine
+            .instructions
+            .iter()
+            .flat_map(|instr| &instr.instruction.links)
+            .chain(&machine.links);
+        for link in links.filter(|link| link.to.instance == submachine.name) {
+            if !linked.contains(&link.to.callable) {
+                linked.push(link.to.callable.clone());
+            }
+        }
+
+        stack.push(ty.clone());
+        children.push(build_node(
+            asm,
+            Some(submachine.name.clone()),
+            ty,
+            child,
+            submachine.args.iter().map(|arg| arg.to_string()).collect(),
+            linked,
+            stack,
+        ));
+        stack.pop();
+    }
+
+    MachineNode {
+        instance,
+        machine: machine_name,
+        min_degree: machine.degree.min.as_ref().map(|e| e.to_string()),
+        max_degree: machine.degree.max.as_ref().map(|e| e.to_string()),
+        arguments,
+        operations,
+        children,
+    }
+}
@@ -1,172 +1,323 @@
 mod analyzer;
+mod budget;
+mod call_hierarchy;
+mod check;
+mod code_lens;
+mod compile;
+mod completion;
+mod dump;
+mod eval;
+mod folding;
+mod highlight;
 mod hover;
+mod index_cache;
+mod lints;
+mod literal;
+mod logging;
+mod machine_tree;
+mod panic;
 mod parser;
+mod pil_document;
+mod progress;
+mod project;
+mod queries;
+mod scheduler;
+mod signature_help;
+mod soundness;
 mod span;
 mod symbol;
+mod transport;
+mod workspace;
+mod workspace_symbols;
 
 use powdr_number::{FieldElement, GoldilocksField};
-use std::collections::HashMap;
-use std::fs;
-use std::path::Path;
-use std::sync::RwLock;
+use rayon::prelude::*;
+use serde_json::{Value, json};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
 use tower_lsp::jsonrpc::Result;
+use tower_lsp::lsp_types::notification::LogTrace;
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer, LspService, Server};
+use tracing::{debug, error, info, warn};
 
-use crate::analyzer::build_semantic_index;
+use crate::budget::set_time_budget;
+use crate::call_hierarchy::{CallGraph, CallHierarchyProvider};
+use crate::code_lens::{CodeLensProvider, SHOW_PIL_COMMAND};
+use crate::compile::{Lowered, lower, machine_stats};
+use crate::completion::CompletionProvider;
+use crate::folding::FoldingRangeProvider;
+use crate::highlight::DocumentHighlightProvider;
 use crate::hover::HoverProvider;
+use crate::index_cache::IndexCache;
+use crate::lints::quick_fixes;
+use crate::logging::extract_log_options;
+use crate::machine_tree::{
+    MACHINE_TREE_METHOD, MachineNode, MachineTreeParams, build_machine_tree,
+};
+use crate::panic::INTERNAL_ERROR;
 use crate::parser::{AnalyzedDoc, ParseResult};
-use crate::symbol::{SemanticIndex, Symbol, SymbolDetails, SymbolId, SymbolKind};
+use crate::pil_document::{
+    PIL_DOCUMENT_METHOD, PIL_SCHEME, PilDocument, PilDocumentParams, asm_uri,
+};
+use crate::progress::ProgressReporter;
+use crate::project::ProjectCache;
+use crate::queries::{Lowering, Published, QueryDatabase};
+use crate::scheduler::{AnalysisScheduler, Cancellation, DEBOUNCE};
+use crate::signature_help::SignatureHelpProvider;
+use crate::soundness::lowered_underconstrained_columns;
+use crate::symbol::{Symbol, SymbolDetails, SymbolId, SymbolKind};
+use crate::transport::{Transport, parse_transport};
+use crate::workspace::{
+    CONFIGURATION_SECTION, LoadedDocument, WorkspaceFolder, find_source_files, load_document,
+    load_document_cached, owning_folder,
+};
+use crate::workspace_symbols::{WorkspaceSymbolProvider, symbol_information};
 
 #[derive(Debug)]
 struct Backend<T: FieldElement> {
     client: Client,
-    project_cache: RwLock<ProjectCache<T>>,
+    project_cache: Arc<RwLock<ProjectCache>>,
+    database: Arc<Mutex<QueryDatabase<T>>>,
+    /// The analyses requests are answered from, see `Published`.
+    published: Arc<Mutex<Published<T>>>,
+    scheduler: AnalysisScheduler,
+    trace: RwLock<TraceValue>,
+    workspace_folders: RwLock<Vec<WorkspaceFolder>>,
+    client_capabilities: RwLock<ClientCapabilities>,
+    /// Call graphs of the asm documents, with the project cache revision they reflect.
+    call_graphs: Mutex<(Option<u64>, Arc<Vec<Arc<CallGraph>>>)>,
 }
 
-#[derive(Debug, Clone)]
-struct ParsedDocument<T> {
-    analyzed: AnalyzedDoc<T>,
-    text: String,
-    version: i32,
-    semantic_index: SemanticIndex,
-}
-
-#[derive(Debug)]
-struct ProjectCache<T> {
-    documents: HashMap<Url, ParsedDocument<T>>,
-    symbol_locations: HashMap<String, Vec<(Url, SymbolKind)>>,
-}
-
-impl<T> ProjectCache<T> {
-    fn new() -> Self {
-        Self {
-            documents: HashMap::new(),
-            symbol_locations: HashMap::new(),
+impl<T: FieldElement> Backend<T> {
+    /// The call graphs of every analyzed asm document, rebuilt only after the
+    /// project cache changed.
+    fn call_graphs(&self) -> Arc<Vec<Arc<CallGraph>>> {
+        let cache = self.project_cache.read().unwrap();
+        let mut graphs = self.call_graphs.lock().unwrap();
+        if graphs.0 != Some(cache.revision) {
+            let collected = cache.call_graphs().cloned().collect();
+            *graphs = (Some(cache.revision), Arc::new(collected));
         }
+        graphs.1.clone()
     }
 
-    fn update_document(&mut self, uri: Url, doc: ParsedDocument<T>) {
-        self.remove_document_symbols(&uri);
-
-        // Actualizar symbol_locations basado en el nuevo semantic_index
-        for (_, symbol) in doc.semantic_index.symbols.iter() {
-            self.symbol_locations
-                .entry(symbol.name.clone())
-                .or_default()
-                .push((uri.clone(), symbol.kind.clone()));
-        }
-
-        self.documents.insert(uri, doc);
+    /// The call graphs of the documents in the workspace folder owning `uri`,
+    /// so that callers in unrelated folders are not reported. Documents
+    /// outside every folder see all graphs.
+    fn folder_call_graphs(&self, uri: &Url) -> Vec<Arc<CallGraph>> {
+        let graphs = self.call_graphs();
+        let folders = self.workspace_folders.read().unwrap();
+        let Some(folder) = owning_folder(&folders, uri) else {
+            return graphs.to_vec();
+        };
+        graphs
+            .iter()
+            .filter(|graph| folder.contains(&graph.uri))
+            .cloned()
+            .collect()
     }
 
-    fn remove_document_symbols(&mut self, uri: &Url) {
-        for locations in self.symbol_locations.values_mut() {
-            locations.retain(|(doc_uri, _)| doc_uri != uri);
-        }
-
-        self.symbol_locations
-            .retain(|_, locations| !locations.is_empty());
+    /// The text and last published analysis of an open document.
+    fn analyzed_document(&self, uri: &Url) -> Option<(Arc<str>, Arc<AnalyzedDoc<T>>)> {
+        self.published
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .analyzed(uri)
     }
 
-    fn get_symbol_locations(&self, name: &str) -> Vec<(Url, SymbolKind)> {
-        self.symbol_locations.get(name).cloned().unwrap_or_default()
+    /// The text and analysis of an open asm document.
+    fn asm_document(&self, uri: &Url) -> Option<(Arc<str>, Arc<AnalyzedDoc<T>>)> {
+        self.analyzed_document(uri)
+            .filter(|(_, analyzed)| matches!(analyzed.as_ref(), AnalyzedDoc::ASM(_)))
     }
-}
-impl<T: FieldElement> Backend<T> {
-    async fn scan_workspace_folder(&self, folder_uri: Url) -> Result<()> {
-        let folder_path = folder_uri
-            .to_file_path()
-            .map_err(|_| tower_lsp::jsonrpc::Error {
+
+    async fn pil_document(&self, params: PilDocumentParams) -> Result<Option<PilDocument>> {
+        let uri = if params.uri.scheme() == PIL_SCHEME {
+            match asm_uri(&params.uri) {
+                Some(uri) => uri,
+                None => return Ok(None),
+            }
+        } else {
+            params.uri
+        };
+
+        let Some((text, _)) = self.asm_document(&uri) else {
+            return Ok(None);
+        };
+        let Some(lowered) = lowered_pil(&self.published, &uri).await else {
+            return Ok(None);
+        };
+        let pil = lowered
+            .as_ref()
+            .as_ref()
+            .map_err(|errors| tower_lsp::jsonrpc::Error {
                 code: tower_lsp::jsonrpc::ErrorCode::InternalError,
-                message: "Invalid folder URI".to_string().into(),
+                message: errors.join("\n").into(),
                 data: None,
             })?;
 
-        self.scan_directory(&folder_path).await
+        Ok(PilDocument::render(&pil.linked, &uri, &text))
+    }
+
+    async fn set_trace(&self, params: SetTraceParams) {
+        *self.trace.write().unwrap() = params.value;
+    }
+
+    async fn log_trace(&self, message: String, verbose: Option<String>) {
+        let trace = *self.trace.read().unwrap();
+        send_log_trace(&self.client, trace, message, verbose).await;
     }
 
-    async fn scan_directory(&self, dir: &Path) -> Result<()> {
-        self.client
-            .log_message(
-                MessageType::INFO,
-                format!("Scanning directory: {}", dir.display()),
+    /// Analyzes the document in the background. Requests keep being answered
+    /// from the last completed analysis until this one finishes.
+    fn schedule_analysis(&self, uri: Url, text: String, version: i32, delay: Duration) {
+        let client = self.client.clone();
+        let project_cache = self.project_cache.clone();
+        let database = self.database.clone();
+        let published = self.published.clone();
+        let trace = *self.trace.read().unwrap();
+
+        self.scheduler.schedule(uri.clone(), delay, |cancellation| {
+            analyze_document::<T>(
+                client,
+                project_cache,
+                database,
+                published,
+                trace,
+                uri,
+                text,
+                version,
+                cancellation,
             )
-            .await;
-        for entry in fs::read_dir(dir).map_err(|e| tower_lsp::jsonrpc::Error {
-            code: tower_lsp::jsonrpc::ErrorCode::InternalError,
-            message: e.to_string().into(),
-            data: None,
-        })? {
-            let entry = entry.map_err(|e| tower_lsp::jsonrpc::Error {
-                code: tower_lsp::jsonrpc::ErrorCode::InternalError,
-                message: e.to_string().into(),
-                data: None,
-            })?;
-            let path = entry.path();
-
-            if path.is_dir() {
-                Box::pin(self.scan_directory(&path)).await?;
-            } else if let Some(extension) = path.extension() {
-                if extension == "pil" || extension == "asm" {
-                    let content =
-                        fs::read_to_string(&path).map_err(|e| tower_lsp::jsonrpc::Error {
-                            code: tower_lsp::jsonrpc::ErrorCode::InternalError,
-                            message: e.to_string().into(),
-                            data: None,
-                        })?;
-                    let uri =
-                        Url::from_file_path(&path).map_err(|_| tower_lsp::jsonrpc::Error {
-                            code: tower_lsp::jsonrpc::ErrorCode::InternalError,
-                            message: "Invalid file path".to_string().into(),
-                            data: None,
-                        })?;
-
-                    let result = crate::parser::parse::<T>(&content, &uri);
-                    let (semantic_index, log_messages) =
-                        crate::analyzer::build_semantic_index(&result.analyzed, &content);
-
-                    for message in log_messages {
-                        self.client.log_message(MessageType::INFO, message).await;
-                    }
-
-                    let doc = ParsedDocument {
-                        analyzed: result.analyzed,
-                        text: content,
-                        version: 0,
-                        semantic_index,
-                    };
-
-                    self.project_cache
-                        .write()
-                        .unwrap()
-                        .update_document(uri, doc);
-                }
-            }
+        });
+    }
+
+    /// Reads the `powdr` configuration section of each folder, if the client
+    /// supports `workspace/configuration`.
+    async fn load_folder_configs(&self, folders: &mut [WorkspaceFolder]) {
+        let supported = self
+            .client_capabilities
+            .read()
+            .unwrap()
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.configuration)
+            .unwrap_or(false);
+        if !supported || folders.is_empty() {
+            return;
+        }
+
+        let items = folders
+            .iter()
+            .map(|folder| ConfigurationItem {
+                scope_uri: Some(folder.uri.clone()),
+                section: Some(CONFIGURATION_SECTION.to_string()),
+            })
+            .collect();
+        let Ok(values) = self.client.configuration(items).await else {
+            return;
+        };
+
+        for (folder, value) in folders.iter_mut().zip(values) {
+            folder.config = serde_json::from_value(value).unwrap_or_default();
+        }
+    }
+
+    fn index_folders(&self, folders: &[WorkspaceFolder]) {
+        let work_done_progress = self
+            .client_capabilities
+            .read()
+            .unwrap()
+            .window
+            .as_ref()
+            .and_then(|window| window.work_done_progress)
+            .unwrap_or(false);
+
+        tokio::spawn(index_workspace::<T>(
+            self.client.clone(),
+            self.project_cache.clone(),
+            folders.iter().flat_map(WorkspaceFolder::roots).collect(),
+            work_done_progress,
+        ));
+    }
+
+    async fn machine_tree(&self, params: MachineTreeParams) -> Result<Option<MachineNode>> {
+        let Some((_, analyzed)) = self.asm_document(&params.text_document.uri) else {
+            return Ok(None);
+        };
+
+        match analyzed.as_ref() {
+            AnalyzedDoc::ASM(asm) => Ok(build_machine_tree(
+                asm,
+                params.machine.as_deref().unwrap_or("Main"),
+            )),
+            AnalyzedDoc::PIL(_) => Ok(None),
         }
-        Ok(())
     }
 }
 #[tower_lsp::async_trait]
 impl<T: FieldElement> LanguageServer for Backend<T> {
     async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
-        self.client
-            .log_message(MessageType::INFO, "Starting workspace initialization...")
-            .await;
+        *self.trace.write().unwrap() = params.trace.unwrap_or_default();
+        info!("starting workspace initialization");
 
-        if let Some(workspace_folders) = params.workspace_folders {
-            for folder in workspace_folders {
-                self.scan_workspace_folder(folder.uri).await?;
-            }
-        }
+        // Indexing starts once the handshake is done, see `initialized`.
+        #[allow(deprecated)]
+        let folders = match (params.workspace_folders, params.root_uri) {
+            (Some(folders), _) => folders
+                .into_iter()
+                .map(|folder| WorkspaceFolder::new(folder.uri, folder.name))
+                .collect(),
+            (None, Some(root)) => vec![WorkspaceFolder::from_root(root)],
+            (None, None) => vec![],
+        };
+        *self.workspace_folders.write().unwrap() = folders;
+        *self.client_capabilities.write().unwrap() = params.capabilities;
 
-        self.client
-            .log_message(MessageType::INFO, "Workspace initialization completed")
-            .await;
+        if let Some(timeout) = params
+            .initialization_options
+            .as_ref()
+            .and_then(|options| options.get("analysisTimeoutMs"))
+            .and_then(Value::as_u64)
+        {
+            set_time_budget(Duration::from_millis(timeout));
+        }
 
         Ok(InitializeResult {
             capabilities: ServerCapabilities {
                 hover_provider: Some(HoverProviderCapability::Simple(true)),
+                completion_provider: Some(CompletionOptions {
+                    resolve_provider: Some(false),
+                    ..Default::default()
+                }),
+                signature_help_provider: Some(SignatureHelpOptions {
+                    trigger_characters: Some(vec!["(".to_string(), ",".to_string()]),
+                    retrigger_characters: None,
+                    work_done_progress_options: Default::default(),
+                }),
+                folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+                document_highlight_provider: Some(OneOf::Left(true)),
+                definition_provider: Some(OneOf::Left(true)),
+                workspace_symbol_provider: Some(OneOf::Left(true)),
+                workspace: Some(WorkspaceServerCapabilities {
+                    workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                        supported: Some(true),
+                        change_notifications: Some(OneOf::Left(true)),
+                    }),
+                    file_operations: None,
+                }),
+                call_hierarchy_provider: Some(CallHierarchyServerCapability::Simple(true)),
+                code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+                code_lens_provider: Some(CodeLensOptions {
+                    resolve_provider: Some(false),
+                }),
+                execute_command_provider: Some(ExecuteCommandOptions {
+                    commands: vec![SHOW_PIL_COMMAND.to_string()],
+                    ..Default::default()
+                }),
                 text_document_sync: Some(TextDocumentSyncCapability::Kind(
                     TextDocumentSyncKind::FULL,
                 )),
@@ -177,103 +328,185 @@ impl<T: FieldElement> LanguageServer for Backend<T> {
     }
 
     async fn initialized(&self, _: InitializedParams) {
-        self.client
-            .log_message(MessageType::INFO, "Powdr LSP initialized!")
+        info!("powdr LSP initialized");
+        self.log_trace("Powdr LSP initialized".to_string(), None)
             .await;
+
+        let mut folders = self.workspace_folders.read().unwrap().clone();
+        self.load_folder_configs(&mut folders).await;
+        *self.workspace_folders.write().unwrap() = folders.clone();
+
+        self.index_folders(&folders);
     }
 
-    async fn did_open(&self, params: DidOpenTextDocumentParams) {
-        let uri = params.text_document.uri;
-        let text = params.text_document.text;
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        let removed: Vec<WorkspaceFolder> = {
+            let mut folders = self.workspace_folders.write().unwrap();
+            let (removed, kept): (Vec<_>, Vec<_>) =
+                folders.drain(..).partition(|folder: &WorkspaceFolder| {
+                    params.event.removed.iter().any(|r| r.uri == folder.uri)
+                });
+            *folders = kept;
+            removed
+        };
 
-        let result = crate::parser::parse::<T>(&text, &uri);
-        let (semantic_index, log_messages) =
-            crate::analyzer::build_semantic_index(&result.analyzed, &text);
+        // Documents stay if another folder, e.g. an enclosing one, still owns them.
+        if !removed.is_empty() {
+            let folders = self.workspace_folders.read().unwrap().clone();
+            self.project_cache.write().unwrap().remove_documents(|uri| {
+                removed.iter().any(|folder| folder.contains(uri))
+                    && !folders.iter().any(|folder| folder.contains(uri))
+            });
+            info!(count = removed.len(), "removed workspace folders");
+        }
 
-        for message in log_messages {
-            self.client.log_message(MessageType::INFO, message).await;
+        let mut added: Vec<WorkspaceFolder> = params
+            .event
+            .added
+            .into_iter()
+            .map(|folder| WorkspaceFolder::new(folder.uri, folder.name))
+            .collect();
+        if added.is_empty() {
+            return;
         }
+        self.load_folder_configs(&mut added).await;
+        self.workspace_folders
+            .write()
+            .unwrap()
+            .extend(added.iter().cloned());
+
+        info!(count = added.len(), "added workspace folders");
+        self.index_folders(&added);
+    }
 
-        let doc = ParsedDocument {
-            analyzed: result.analyzed,
-            text: text.clone(),
-            version: params.text_document.version,
-            semantic_index,
+    async fn symbol(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> Result<Option<Vec<SymbolInformation>>> {
+        // Files without text in memory are read after the cache is released.
+        let matches = {
+            let folders = self.workspace_folders.read().unwrap().clone();
+            let cache = self.project_cache.read().unwrap();
+            WorkspaceSymbolProvider::new(&cache, &folders).get_matches(&params.query)
         };
+        let symbols = tokio::task::spawn_blocking(move || symbol_information(matches))
+            .await
+            .unwrap_or_default();
+        Ok(Some(symbols))
+    }
 
-        self.project_cache
-            .write()
-            .unwrap()
-            .update_document(uri.clone(), doc);
+    async fn did_open(&self, params: DidOpenTextDocumentParams) {
+        let uri = params.text_document.uri;
+        let text = params.text_document.text;
 
-        self.client
-            .publish_diagnostics(uri, result.diagnostics, None)
-            .await;
+        // Generated PIL documents are served by `powdr/pilDocument`, not analyzed.
+        if uri.scheme() == PIL_SCHEME {
+            return;
+        }
+
+        self.schedule_analysis(uri, text, params.text_document.version, Duration::ZERO);
     }
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
         let uri = params.text_document.uri;
         let text = params.content_changes[0].text.clone();
 
-        let result = crate::parser::parse::<T>(&text, &uri);
-        let (semantic_index, log_messages) =
-            crate::analyzer::build_semantic_index(&result.analyzed, &text);
+        if uri.scheme() == PIL_SCHEME {
+            return;
+        }
+
+        self.schedule_analysis(uri, text, params.text_document.version, DEBOUNCE);
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        let uri = params.text_document.uri;
 
-        for message in log_messages {
-            self.client.log_message(MessageType::INFO, message).await;
+        if uri.scheme() == PIL_SCHEME {
+            return;
         }
 
-        let doc = ParsedDocument {
-            analyzed: result.analyzed,
-            text: text.clone(),
-            version: params.text_document.version,
-            semantic_index,
-        };
+        self.scheduler.cancel(&uri);
+        self.database
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&uri);
+        self.published
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&uri);
 
-        self.project_cache
-            .write()
+        let in_workspace = self
+            .workspace_folders
+            .read()
             .unwrap()
-            .update_document(uri.clone(), doc);
-
-        self.client
-            .publish_diagnostics(uri, result.diagnostics, None)
-            .await;
+            .iter()
+            .any(|folder| folder.contains(&uri));
+        tokio::spawn(reload_closed_document::<T>(
+            self.client.clone(),
+            self.project_cache.clone(),
+            uri,
+            in_workspace,
+        ));
     }
 
-    // async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
-    //     let position = params.text_document_position_params.position;
-    //     let uri = params.text_document_position_params.text_document.uri;
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        let position = params.text_document_position_params.position;
+        let uri = params.text_document_position_params.text_document.uri;
+
+        debug!(?position, %uri, "hover requested");
+
+        let doc = {
+            let cache = self.project_cache.read().unwrap();
+            match cache.documents.get(&uri) {
+                Some(doc) => doc.clone(),
+                None => return Ok(None),
+            }
+        };
+
+        let Some((_, analyzed)) = self.analyzed_document(&uri) else {
+            return Ok(None);
+        };
+        let Some(constants) = self
+            .published
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .constant_values(&uri)
+        else {
+            return Ok(None);
+        };
 
-    //     let doc = {
-    //         let cache = self.project_cache.read().unwrap();
-    //         match cache.documents.get(&uri) {
-    //             Some(doc) => doc.clone(),
-    //             None => return Ok(None),
-    //         }
-    //     };
+        // Hovering a constant evaluates it, keep that off the executor threads.
+        let hover_result = tokio::task::spawn_blocking(move || {
+            HoverProvider::new(&doc.text, &analyzed, &doc.semantic_index, &constants)
+                .get_hover(position)
+        })
+        .await
+        .ok()
+        .flatten();
+        debug!(found = hover_result.is_some(), "hover answered");
 
-    //     let hover_provider = HoverProvider::new(
-    //         doc.text.clone(),
-    //         doc.ast.clone(),
-    //         doc.semantic_index.clone(),
-    //     );
+        Ok(hover_result)
+    }
 
-    //     let hover_result = hover_provider.get_hover(position);
-    //     Ok(hover_result)
-    // }
+    async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
+        let uri = params.text_document_position.text_document.uri;
 
-    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        let doc = {
+            let cache = self.project_cache.read().unwrap();
+            match cache.documents.get(&uri) {
+                Some(doc) => doc.clone(),
+                None => return Ok(None),
+            }
+        };
+
+        let provider = CompletionProvider::new(&doc.semantic_index);
+        Ok(Some(CompletionResponse::Array(provider.get_completions())))
+    }
+
+    async fn signature_help(&self, params: SignatureHelpParams) -> Result<Option<SignatureHelp>> {
         let position = params.text_document_position_params.position;
         let uri = params.text_document_position_params.text_document.uri;
 
-        // First log message
-        self.client
-            .log_message(
-                MessageType::INFO,
-                format!("Hover requested at position {:?} in {}", position, uri),
-            )
-            .await;
-
         let doc = {
             let cache = self.project_cache.read().unwrap();
             match cache.documents.get(&uri) {
@@ -282,41 +515,139 @@ impl<T: FieldElement> LanguageServer for Backend<T> {
             }
         };
 
-        self.client
-            .log_message(MessageType::INFO, "Document found, creating hover provider")
-            .await;
+        let provider = SignatureHelpProvider::new(&doc.text, &doc.semantic_index);
+        Ok(provider.get_signature_help(position))
+    }
 
-        let hover_provider = HoverProvider::new(
-            doc.text.clone(),
-            doc.analyzed.clone(), // TODO: this is ugly
-            doc.semantic_index.clone(),
-        );
+    async fn folding_range(&self, params: FoldingRangeParams) -> Result<Option<Vec<FoldingRange>>> {
+        let uri = params.text_document.uri;
 
-        let (hover_result, log_messages) = hover_provider.get_hover(position);
+        let text = {
+            let cache = self.project_cache.read().unwrap();
+            match cache.documents.get(&uri) {
+                Some(doc) => doc.text.clone(),
+                None => return Ok(None),
+            }
+        };
 
-        for message in log_messages {
-            self.client.log_message(MessageType::INFO, message).await;
-        }
+        let provider = FoldingRangeProvider::new(&text);
+        Ok(Some(provider.get_folding_ranges(&uri)))
+    }
 
-        match &hover_result {
-            Some(hover) => {
-                if let HoverContents::Markup(content) = &hover.contents {
-                    self.client
-                        .log_message(
-                            MessageType::INFO,
-                            format!("Hover content generated: {}", content.value),
-                        )
-                        .await;
-                }
+    async fn document_highlight(
+        &self,
+        params: DocumentHighlightParams,
+    ) -> Result<Option<Vec<DocumentHighlight>>> {
+        let position = params.text_document_position_params.position;
+        let uri = params.text_document_position_params.text_document.uri;
+
+        let doc = {
+            let cache = self.project_cache.read().unwrap();
+            match cache.documents.get(&uri) {
+                Some(doc) => doc.clone(),
+                None => return Ok(None),
             }
-            None => {
-                self.client
-                    .log_message(MessageType::INFO, "No hover information found")
-                    .await;
+        };
+
+        let provider = DocumentHighlightProvider::new(&doc.text, &doc.semantic_index);
+        Ok(provider.get_highlights(position))
+    }
+
+    async fn prepare_call_hierarchy(
+        &self,
+        params: CallHierarchyPrepareParams,
+    ) -> Result<Option<Vec<CallHierarchyItem>>> {
+        let position = params.text_document_position_params.position;
+        let uri = params.text_document_position_params.text_document.uri;
+
+        let graphs = self.call_graphs();
+        let provider = CallHierarchyProvider::new(&graphs);
+        Ok(provider.prepare(&uri, position))
+    }
+
+    async fn incoming_calls(
+        &self,
+        params: CallHierarchyIncomingCallsParams,
+    ) -> Result<Option<Vec<CallHierarchyIncomingCall>>> {
+        let graphs = self.folder_call_graphs(&params.item.uri);
+        let provider = CallHierarchyProvider::new(&graphs);
+        Ok(Some(provider.incoming_calls(&params.item)))
+    }
+
+    async fn outgoing_calls(
+        &self,
+        params: CallHierarchyOutgoingCallsParams,
+    ) -> Result<Option<Vec<CallHierarchyOutgoingCall>>> {
+        let graphs = self.call_graphs();
+        let provider = CallHierarchyProvider::new(&graphs);
+        Ok(Some(provider.outgoing_calls(&params.item)))
+    }
+
+    async fn code_lens(&self, params: CodeLensParams) -> Result<Option<Vec<CodeLens>>> {
+        let uri = params.text_document.uri;
+
+        let Some((text, analyzed)) = self.asm_document(&uri) else {
+            return Ok(None);
+        };
+        let AnalyzedDoc::ASM(asm) = analyzed.as_ref() else {
+            return Ok(None);
+        };
+
+        // Lowering can fail on files without an entry machine, the lenses to
+        // show the generated PIL are still offered in that case.
+        let stats = lowered_pil(&self.published, &uri)
+            .await
+            .and_then(|lowered| {
+                let pil = lowered.as_ref().as_ref().ok()?;
+                Some(machine_stats(asm, &pil.analyzed))
+            });
+
+        let provider = CodeLensProvider::new(&text, stats.as_ref());
+        Ok(Some(provider.get_code_lenses(&uri)))
+    }
+
+    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        let actions = quick_fixes(&params.text_document.uri, &params.context.diagnostics);
+        Ok(Some(actions))
+    }
+
+    async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<Value>> {
+        match params.command.as_str() {
+            SHOW_PIL_COMMAND => {
+                let uri = params
+                    .arguments
+                    .first()
+                    .and_then(|arg| serde_json::from_value::<Url>(arg.clone()).ok())
+                    .ok_or_else(|| {
+                        tower_lsp::jsonrpc::Error::invalid_params("Expected a document URI")
+                    })?;
+
+                // The client opens the returned `powdr-pil://` document and fetches
+                // its contents through `powdr/pilDocument`.
+                Ok(self
+                    .pil_document(PilDocumentParams { uri })
+                    .await?
+                    .map(|doc| json!(doc.uri)))
             }
+            _ => Err(tower_lsp::jsonrpc::Error::method_not_found()),
         }
+    }
 
-        Ok(hover_result)
+    async fn goto_definition(
+        &self,
+        params: GotoDefinitionParams,
+    ) -> Result<Option<GotoDefinitionResponse>> {
+        let position = params.text_document_position_params.position;
+        let uri = params.text_document_position_params.text_document.uri;
+
+        if uri.scheme() != PIL_SCHEME {
+            return Ok(None);
+        }
+
+        let doc = self.pil_document(PilDocumentParams { uri }).await?;
+        Ok(doc
+            .and_then(|doc| doc.source_at(position))
+            .map(GotoDefinitionResponse::Scalar))
     }
 
     async fn shutdown(&self) -> Result<()> {
@@ -325,14 +656,439 @@ impl<T: FieldElement> LanguageServer for Backend<T> {
 }
 #[tokio::main]
 async fn main() {
-    let stdin = tokio::io::stdin();
-    let stdout = tokio::io::stdout();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (log_options, args) = match extract_log_options(args) {
+        Ok(result) => result,
+        Err(message) => {
+            eprintln!("{message}");
+            std::process::exit(2);
+        }
+    };
+    if let Err(message) = crate::logging::init(&log_options) {
+        eprintln!("{message}");
+        std::process::exit(2);
+    }
+
+    match args.first().map(String::as_str) {
+        Some("check") => std::process::exit(crate::check::run::<GoldilocksField>(&args[1..])),
+        Some("index") => std::process::exit(crate::dump::run::<GoldilocksField>(&args[1..])),
+        _ => {}
+    }
+
+    let transport = match parse_transport(&args) {
+        Ok(transport) => transport,
+        Err(message) => {
+            eprintln!("{message}");
+            std::process::exit(2);
+        }
+    };
+
+    // Editors attached to the same process share the index.
+    let project_cache = Arc::new(RwLock::new(ProjectCache::new()));
+    let database = Arc::new(Mutex::new(QueryDatabase::new()));
+    let published = Arc::new(Mutex::new(Published::new()));
+
+    match transport {
+        Transport::Stdio => {
+            serve(
+                tokio::io::stdin(),
+                tokio::io::stdout(),
+                project_cache,
+                database,
+                published,
+            )
+            .await;
+        }
+        Transport::Listen(port) => {
+            let listener = match TcpListener::bind(("127.0.0.1", port)).await {
+                Ok(listener) => listener,
+                Err(e) => {
+                    eprintln!("Cannot listen on port {port}: {e}");
+                    std::process::exit(2);
+                }
+            };
+            loop {
+                let Ok((stream, _)) = listener.accept().await else {
+                    continue;
+                };
+                let (read, write) = tokio::io::split(stream);
+                tokio::spawn(serve(
+                    read,
+                    write,
+                    project_cache.clone(),
+                    database.clone(),
+                    published.clone(),
+                ));
+            }
+        }
+        Transport::Connect(address) => {
+            let stream = match TcpStream::connect(&address).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("Cannot connect to {address}: {e}");
+                    std::process::exit(2);
+                }
+            };
+            let (read, write) = tokio::io::split(stream);
+            serve(read, write, project_cache, database, published).await;
+        }
+        Transport::Pipe(path) => {
+            #[cfg(unix)]
+            let stream = tokio::net::UnixStream::connect(&path).await;
+            #[cfg(windows)]
+            let stream = tokio::net::windows::named_pipe::ClientOptions::new().open(&path);
+
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("Cannot connect to {}: {e}", path.display());
+                    std::process::exit(2);
+                }
+            };
+            let (read, write) = tokio::io::split(stream);
+            serve(read, write, project_cache, database, published).await;
+        }
+    }
+}
+
+/// Sends a `$/logTrace` notification if the client asked for traces. The
+/// verbose part is only sent for `verbose` tracing.
+async fn send_log_trace(
+    client: &Client,
+    trace: TraceValue,
+    message: String,
+    verbose: Option<String>,
+) {
+    if trace == TraceValue::Off {
+        return;
+    }
+
+    client
+        .send_notification::<LogTrace>(LogTraceParams {
+            message,
+            verbose: verbose.filter(|_| trace == TraceValue::Verbose),
+        })
+        .await;
+}
+
+/// The PIL lowered from the published analysis of an asm document. Lowering
+/// runs on a blocking thread, within the time budget and without holding any
+/// lock, and its outcome is kept until the text changes.
+async fn lowered_pil<T: FieldElement>(
+    published: &Arc<Mutex<Published<T>>>,
+    uri: &Url,
+) -> Option<Arc<Lowered<T>>> {
+    let lowering = published
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .lowering(uri)?;
+    let (text, analyzed) = match lowering {
+        Lowering::Done(lowered) => return Some(lowered),
+        Lowering::Pending { text, analyzed } => (text, analyzed),
+    };
+
+    // A request cancelled while lowering drops this future, the lowering
+    // itself runs to the end and its outcome is dropped.
+    let key = uri.clone();
+    let lowered = tokio::task::spawn_blocking(move || match analyzed.as_ref() {
+        AnalyzedDoc::ASM(asm) => lower::<T>(&key, asm.clone()),
+        AnalyzedDoc::PIL(_) => Err(vec!["PIL documents are not lowered".to_string()]),
+    })
+    .await
+    .ok()?;
+
+    let lowered = Arc::new(lowered);
+    published
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .set_lowered(uri, &text, lowered.clone());
+    Some(lowered)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn analyze_document<T: FieldElement>(
+    client: Client,
+    project_cache: Arc<RwLock<ProjectCache>>,
+    database: Arc<Mutex<QueryDatabase<T>>>,
+    published: Arc<Mutex<Published<T>>>,
+    trace: TraceValue,
+    uri: Url,
+    text: String,
+    version: i32,
+    cancellation: Cancellation,
+) {
+    let started = Instant::now();
+
+    // Parsing is CPU bound, keep it off the executor threads. If this analysis
+    // is superseded while parsing, the blocking work stops at the next stage
+    // and its result is dropped here.
+    let analysis = tokio::task::spawn_blocking({
+        let uri = uri.clone();
+        let cancellation = cancellation.clone();
+        let database = database.clone();
+        move || {
+            // Stages catch panics themselves, a poisoned lock only means one
+            // escaped between them and the memoized state is still usable.
+            let mut database = database.lock().unwrap_or_else(PoisonError::into_inner);
+            // Superseded while waiting for the lock.
+            if cancellation.is_cancelled() {
+                return None;
+            }
+            database.set_text(&uri, text.into());
+            let document = database.document(&uri, version, &cancellation)?;
+            let (_, analyzed) = database.analyzed(&uri)?;
+            Some((document, analyzed))
+        }
+    })
+    .await;
+    let ((doc, mut diagnostics), analyzed) = match analysis {
+        Ok(Some(analysis)) => analysis,
+        Ok(None) => return,
+        Err(e) if e.is_cancelled() => return,
+        Err(e) => {
+            // The previous analysis stays in the cache.
+            error!(%uri, error = %e, "analysis failed");
+            let diagnostic = Diagnostic {
+                range: Range::default(),
+                severity: Some(DiagnosticSeverity::ERROR),
+                message: format!("{INTERNAL_ERROR}: {e}"),
+                source: Some("powdr".to_string()),
+                ..Default::default()
+            };
+            client
+                .publish_diagnostics(uri, vec![diagnostic], Some(version))
+                .await;
+            return;
+        }
+    };
+
+    let elapsed = started.elapsed();
+    info!(
+        %uri,
+        version,
+        symbols = doc.semantic_index.symbols.len(),
+        diagnostics = diagnostics.len(),
+        ?elapsed,
+        "analyzed document"
+    );
+
+    let verbose = format!(
+        "{} symbols, {} diagnostics",
+        doc.semantic_index.symbols.len(),
+        diagnostics.len()
+    );
+    let text = doc.text.clone();
+
+    {
+        let mut cache = project_cache.write().unwrap();
+        // Superseded, or the document was closed, while analyzing.
+        if cancellation.is_cancelled() {
+            return;
+        }
+        if cache
+            .documents
+            .get(&uri)
+            .is_some_and(|doc| doc.version > version)
+        {
+            debug!(%uri, version, "dropping stale analysis");
+            return;
+        }
+
+        published
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .publish(&uri, doc.text.clone(), analyzed);
+        cache.update_document(uri.clone(), doc);
+    }
+
+    send_log_trace(
+        &client,
+        trace,
+        format!("Analyzed {uri} in {elapsed:?}"),
+        Some(verbose),
+    )
+    .await;
+
+    client
+        .publish_diagnostics(uri.clone(), diagnostics.clone(), Some(version))
+        .await;
+
+    // Asm documents are checked for soundness on their lowered PIL, which is
+    // too slow to produce under the database lock on every edit. It is lowered
+    // once the analysis is published, kept until the text changes, and its
+    // findings are published together with the diagnostics above.
+    let Some(lowered) = lowered_pil(&published, &uri).await else {
+        return;
+    };
+    let findings = lowered_underconstrained_columns(&text, &lowered);
+    if findings.is_empty() || cancellation.is_cancelled() {
+        return;
+    }
+    diagnostics.extend(findings);
+    client
+        .publish_diagnostics(uri, diagnostics, Some(version))
+        .await;
+}
+
+/// Replaces the index of a closed document, which may reflect unsaved edits,
+/// by the index of the file on disk. Documents outside the workspace, or
+/// whose file is gone, leave the project cache.
+async fn reload_closed_document<T: FieldElement>(
+    client: Client,
+    project_cache: Arc<RwLock<ProjectCache>>,
+    uri: Url,
+    in_workspace: bool,
+) {
+    let reloaded = match uri.to_file_path() {
+        Ok(path) if in_workspace => tokio::task::spawn_blocking(move || load_document::<T>(&path))
+            .await
+            .ok()
+            .and_then(|loaded| loaded.ok()),
+        _ => None,
+    };
+
+    {
+        let mut cache = project_cache.write().unwrap();
+        match reloaded {
+            Some((_, doc)) => cache.update_document(uri.clone(), doc),
+            None => cache.remove_documents(|document| *document == uri),
+        }
+    }
+
+    // Only open documents have diagnostics.
+    client.publish_diagnostics(uri, vec![], None).await;
+}
+
+/// Indexes every source file of the workspace on a thread pool. Files the
+/// editor opened in the meantime are left alone, unreadable files are skipped.
+async fn index_workspace<T: FieldElement>(
+    client: Client,
+    project_cache: Arc<RwLock<ProjectCache>>,
+    folders: Vec<Url>,
+    work_done_progress: bool,
+) {
+    let started = Instant::now();
+    let progress = if work_done_progress {
+        ProgressReporter::begin(&client, "Indexing powdr files").await
+    } else {
+        None
+    };
+
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let indexing = tokio::task::spawn_blocking(move || {
+        let mut failures = Vec::new();
+        let mut files = Vec::new();
+        for folder in folders {
+            let Ok(dir) = folder.to_file_path() else {
+                failures.push(format!("{folder}: not a file URI"));
+                continue;
+            };
+            match find_source_files(&dir) {
+                Ok(found) => files.extend(found),
+                Err(e) => failures.push(format!("{}: {e}", dir.display())),
+            }
+        }
+        let _ = sender.send(files.len());
+
+        let index_cache = IndexCache::open();
+        let results: Vec<_> = files
+            .into_par_iter()
+            .map(|path| {
+                let result = match &index_cache {
+                    Some(index_cache) => load_document_cached::<T>(&path, index_cache),
+                    None => load_document::<T>(&path)
+                        .map(|(uri, doc)| (uri, LoadedDocument::Analyzed(doc))),
+                };
+                let _ = sender.send(1);
+                result.map_err(|e| format!("{}: {e}", path.display()))
+            })
+            .collect();
+
+        let mut documents = Vec::new();
+        for result in results {
+            match result {
+                Ok(document) => documents.push(document),
+                Err(failure) => failures.push(failure),
+            }
+        }
+        (documents, failures)
+    });
+
+    // The first message is the number of files, every further one a finished file.
+    let total = receiver.recv().await.unwrap_or(0);
+    let mut done = 0;
+    while receiver.recv().await.is_some() {
+        done += 1;
+        if let Some(progress) = &progress {
+            progress.report(done, total).await;
+        }
+    }
+
+    let Ok((documents, failures)) = indexing.await else {
+        return;
+    };
+
+    let indexed = documents.len();
+    {
+        let mut cache = project_cache.write().unwrap();
+        for (uri, loaded) in documents {
+            if cache.documents.contains_key(&uri) {
+                continue;
+            }
+            match loaded {
+                LoadedDocument::Analyzed(doc) => cache.update_document(uri, doc),
+                LoadedDocument::Cached(index) => cache.update_cached_index(uri, index),
+            }
+        }
+    }
+
+    info!(indexed, skipped = failures.len(), elapsed = ?started.elapsed(), "indexed workspace");
+    if let Some(progress) = progress {
+        progress.end(format!("Indexed {indexed} files")).await;
+    }
+
+    if !failures.is_empty() {
+        for failure in &failures {
+            warn!(%failure, "skipped file");
+        }
+        client
+            .show_message(
+                MessageType::WARNING,
+                format!(
+                    "Skipped {} unreadable file(s):\n{}",
+                    failures.len(),
+                    failures.join("\n")
+                ),
+            )
+            .await;
+    }
+}
 
+async fn serve<I, O>(
+    input: I,
+    output: O,
+    project_cache: Arc<RwLock<ProjectCache>>,
+    database: Arc<Mutex<QueryDatabase<GoldilocksField>>>,
+    published: Arc<Mutex<Published<GoldilocksField>>>,
+) where
+    I: AsyncRead + Unpin,
+    O: AsyncWrite,
+{
     let (service, socket) = LspService::build(|client| Backend::<GoldilocksField> {
         client,
-        project_cache: RwLock::new(ProjectCache::new()),
+        project_cache,
+        database,
+        published,
+        scheduler: AnalysisScheduler::new(),
+        trace: RwLock::new(TraceValue::Off),
+        workspace_folders: RwLock::new(Vec::new()),
+        client_capabilities: RwLock::new(ClientCapabilities::default()),
+        call_graphs: Mutex::new((None, Arc::new(Vec::new()))),
     })
+    .custom_method("$/setTrace", Backend::set_trace)
+    .custom_method(MACHINE_TREE_METHOD, Backend::machine_tree)
+    .custom_method(PIL_DOCUMENT_METHOD, Backend::pil_document)
     .finish();
 
-    Server::new(stdin, stdout, socket).serve(service).await;
+    Server::new(input, output, socket).serve(service).await;
 }
@@ -0,0 +1,21 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+
+/// Prefix of diagnostics reporting a panic inside powdr.
+pub const INTERNAL_ERROR: &str = "internal analyzer error";
+
+/// Runs `f`, turning a panic into its message. The powdr crates can panic on
+/// unusual input, which must not take down the server.
+pub fn catch_panic<R>(f: impl FnOnce() -> R) -> Result<R, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| panic_message(&*payload))
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
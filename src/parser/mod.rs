@@ -2,18 +2,29 @@ use std::path::PathBuf;
 
 use powdr_ast::analyzed::Analyzed;
 use powdr_ast::asm_analysis::AnalysisASMFile;
-use powdr_parser_util::Error as PowdrError;
+use powdr_ast::parsed::PILFile;
+use powdr_ast::parsed::asm::ASMProgram;
+use powdr_parser_util::{Error as PowdrError, SourceRef};
 
 use powdr_number::FieldElement;
 use powdr_parser;
 use powdr_pil_analyzer;
 use tower_lsp::lsp_types::*;
+use tracing::error;
+
+use crate::budget::with_time_budget;
+use crate::eval::{MAX_STEPS, check_steps};
+use crate::panic::{INTERNAL_ERROR, catch_panic};
+use crate::span::Span;
 
 pub struct ParseResult<T> {
     pub diagnostics: Vec<Diagnostic>,
     pub analyzed: AnalyzedDoc<T>,
+    /// The AST before import resolution, if the document parsed.
+    pub parsed: Option<ParsedAst>,
 }
 
+#[derive(Debug, PartialEq)]
 pub struct Error {
     pub message: String,
     pub source_pos: SourcePos,
@@ -51,6 +62,7 @@ impl From<Error> for String {
     }
 }
 
+#[derive(Debug, PartialEq)]
 pub struct SourcePos {
     pub start: usize,
     pub end: usize,
@@ -72,74 +84,142 @@ pub enum AnalyzedDoc<T> {
     PIL(Analyzed<T>),
 }
 
+/// Output of the first pipeline stage, before imports are resolved.
+#[derive(Debug, Clone)]
+pub enum ParsedAst {
+    ASM(ASMProgram),
+    PIL(PILFile),
+}
+
 pub fn parse<T: FieldElement>(content: &str, uri: &Url) -> ParseResult<T> {
-    let result = if uri.path().ends_with(".asm") {
-        match parse_asm(uri.path(), content) {
-            Ok(asm) => Ok(AnalyzedDoc::ASM(asm)),
-            Err(e) => Err(e),
-        }
-    } else {
-        match parse_pil::<T>(content) {
-            Ok(pil) => Ok(AnalyzedDoc::PIL(pil)),
-            Err(e) => Err(e.into()),
-        }
+    let parsed = parse_ast(content, uri);
+    let result = match &parsed {
+        Ok(ParsedAst::ASM(program)) => resolve_imports(uri, program.clone())
+            .and_then(|resolved| analyze_asm(uri, resolved))
+            .map(AnalyzedDoc::ASM),
+        Ok(ParsedAst::PIL(pil)) => analyze_pil::<T>(uri, pil.clone()).map(AnalyzedDoc::PIL),
+        Err(_) => Err(vec![]),
+    };
+    let (parsed, result) = match parsed {
+        Ok(parsed) => (Some(parsed), result),
+        Err(errors) => (None, Err(errors)),
     };
 
     match result {
         Ok(analyzed) => ParseResult {
             diagnostics: vec![],
             analyzed,
+            parsed,
+        },
+        Err(err) => ParseResult {
+            diagnostics: to_diagnostics(&err, content),
+            analyzed: AnalyzedDoc::ASM(AnalysisASMFile::default()), // Default in case of error
+            parsed,
         },
-        Err(err) => {
-            let diagnostics = err
-                .iter()
-                .map(|e| Diagnostic {
-                    range: Range {
-                        start: convert_position(e.source_pos().start, content),
-                        end: convert_position(e.source_pos().end, content),
-                    },
-                    severity: Some(DiagnosticSeverity::ERROR),
-                    message: e.message().to_string(),
-                    source: Some("powdr".to_string()),
-                    ..Default::default()
-                })
-                .collect();
-
-            ParseResult {
-                diagnostics,
-                analyzed: AnalyzedDoc::ASM(AnalysisASMFile::default()), // Default in case of error
-            }
-        }
     }
 }
-fn parse_asm(path: &str, content: &str) -> Result<AnalysisASMFile, Vec<Error>> {
-    let parsed_asm = match powdr_parser::parse_asm(Some(path), content) {
-        Ok(asm) => asm,
-        Err(e) => return Err(vec![e.into()]),
-    };
 
-    let resolved =
-        powdr_importer::load_dependencies_and_resolve(Some(PathBuf::from(path)), parsed_asm)
-            .map_err(|e| vec![e.into()])?;
+pub fn to_diagnostics(errors: &[Error], content: &str) -> Vec<Diagnostic> {
+    errors
+        .iter()
+        .map(|e| Diagnostic {
+            range: Range {
+                start: convert_position(e.source_pos().start, content),
+                end: convert_position(e.source_pos().end, content),
+            },
+            severity: Some(DiagnosticSeverity::ERROR),
+            message: e.message().to_string(),
+            source: Some("powdr".to_string()),
+            ..Default::default()
+        })
+        .collect()
+}
+
+pub fn parse_ast(content: &str, uri: &Url) -> Result<ParsedAst, Vec<Error>> {
+    isolated(|| parse_ast_unchecked(content, uri))
+}
+
+fn parse_ast_unchecked(content: &str, uri: &Url) -> Result<ParsedAst, Vec<Error>> {
+    if uri.path().ends_with(".asm") {
+        powdr_parser::parse_asm(Some(uri.path()), content)
+            .map(ParsedAst::ASM)
+            .map_err(|e| vec![e.into()])
+    } else {
+        powdr_parser::parse(None, content)
+            .map(ParsedAst::PIL)
+            .map_err(|e| vec![e.into()])
+    }
+}
 
-    powdr_analysis::analyze(resolved).map_err(|strings| {
-        strings
-            .into_iter()
-            .map(|message| Error {
-                message,
-                source_pos: SourcePos::unknown(),
+/// Loads the modules declared with `mod` from disk and resolves all paths.
+pub fn resolve_imports(uri: &Url, program: ASMProgram) -> Result<ASMProgram, Vec<Error>> {
+    isolated(|| {
+        powdr_importer::load_dependencies_and_resolve(Some(PathBuf::from(uri.path())), program)
+            .map_err(|e| vec![e.into()])
+    })
+}
+
+pub fn analyze_asm(uri: &Url, resolved: ASMProgram) -> Result<AnalysisASMFile, Vec<Error>> {
+    budgeted(uri, move || {
+        isolated(|| {
+            powdr_analysis::analyze(resolved).map_err(|strings| {
+                strings
+                    .into_iter()
+                    .map(|message| Error {
+                        message,
+                        source_pos: SourcePos::unknown(),
+                    })
+                    .collect()
             })
-            .collect()
+        })
     })
 }
 
-fn parse_pil<T: FieldElement>(content: &str) -> Result<Analyzed<T>, Vec<Error>> {
-    match powdr_pil_analyzer::analyze_string::<T>(content) {
-        Ok(pil) => Ok(pil),
-        Err(e) => Err(e.into_iter().map(|err| err.into()).collect()),
-    }
+/// Analyzes a PIL file. Its compile time expressions are first evaluated with
+/// a step limit, so a definition that does not terminate is reported instead
+/// of being handed to the analyzer, which cannot be stopped.
+pub fn analyze_pil<T: FieldElement>(uri: &Url, pil: PILFile) -> Result<Analyzed<T>, Vec<Error>> {
+    budgeted(uri, move || {
+        isolated(|| {
+            check_steps(&pil).map_err(|exhausted| {
+                vec![Error::new(
+                    format!(
+                        "evaluation of `{}` did not finish within {MAX_STEPS} steps",
+                        exhausted.name
+                    ),
+                    SourcePos::new(exhausted.source.start, exhausted.source.end),
+                )]
+            })
+        })?;
+        isolated(|| {
+            powdr_pil_analyzer::analyze_ast::<T>(pil)
+                .map_err(|e| e.into_iter().map(|err| err.into()).collect())
+        })
+    })
+}
+
+/// Runs a stage that evaluates user code within the time budget of the
+/// document. A timeout is reported at the start of the file.
+fn budgeted<R: Send + 'static>(
+    uri: &Url,
+    stage: impl FnOnce() -> Result<R, Vec<Error>> + Send + 'static,
+) -> Result<R, Vec<Error>> {
+    with_time_budget(uri.as_str(), stage)
+        .unwrap_or_else(|message| Err(vec![Error::new(message, SourcePos::unknown())]))
 }
-fn convert_position(offset: usize, content: &str) -> Position {
+
+/// Runs a pipeline stage, reporting a panic as an error at the start of the file.
+fn isolated<R>(stage: impl FnOnce() -> Result<R, Vec<Error>>) -> Result<R, Vec<Error>> {
+    catch_panic(stage).unwrap_or_else(|message| {
+        error!(%message, "powdr panicked");
+        Err(vec![Error::new(
+            format!("{INTERNAL_ERROR}: {message}"),
+            SourcePos::unknown(),
+        )])
+    })
+}
+
+pub fn convert_position(offset: usize, content: &str) -> Position {
     let content_until_offset = &content[..offset];
     let line = content_until_offset.chars().filter(|&c| c == '\n').count() as u32;
 
@@ -152,3 +232,38 @@ fn convert_position(offset: usize, content: &str) -> Position {
 
     Position::new(line, column)
 }
+
+pub fn position_to_offset(position: Position, content: &str) -> Option<usize> {
+    content.lines().nth(position.line as usize)?;
+
+    let offset = content
+        .lines()
+        .take(position.line as usize)
+        .map(|line| line.len() + 1)
+        .sum::<usize>();
+
+    Some(offset + position.character as usize)
+}
+
+/// Resolves a span of the analyzed program to a location. Imported files carry
+/// their own name and contents, anything else belongs to the document at `uri`.
+pub fn source_location(source: &SourceRef, span: Span, uri: &Url, text: &str) -> Option<Location> {
+    let text = source.file_contents.as_deref().unwrap_or(text);
+    if span.start > span.end || span.end > text.len() {
+        return None;
+    }
+
+    let uri = source
+        .file_name
+        .as_deref()
+        .and_then(|name| Url::from_file_path(name).ok())
+        .unwrap_or_else(|| uri.clone());
+
+    Some(Location::new(
+        uri,
+        Range::new(
+            convert_position(span.start, text),
+            convert_position(span.end, text),
+        ),
+    ))
+}
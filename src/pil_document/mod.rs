@@ -0,0 +1,92 @@
+use crate::parser::source_location;
+use powdr_ast::parsed::{PILFile, SourceReference};
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::*;
+
+pub const PIL_SCHEME: &str = "powdr-pil";
+pub const PIL_DOCUMENT_METHOD: &str = "powdr/pilDocument";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PilDocumentParams {
+    /// Either the asm document or its `powdr-pil://` counterpart.
+    pub uri: Url,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PilDocument {
+    pub uri: Url,
+    pub text: String,
+    pub source_map: Vec<SourceMapping>,
+}
+
+/// Lines of the generated PIL produced by an instruction, link or statement of the asm.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceMapping {
+    pub pil_range: Range,
+    pub source: Location,
+}
+
+/// `file:///a/main.asm` is shown as `powdr-pil:///a/main.asm.pil`.
+pub fn pil_uri(asm_uri: &Url) -> Option<Url> {
+    Url::parse(&format!("{}://{}.pil", PIL_SCHEME, asm_uri.path())).ok()
+}
+
+pub fn asm_uri(pil_uri: &Url) -> Option<Url> {
+    let path = pil_uri.path().strip_suffix(".pil")?;
+    Url::parse(&format!("file://{}", path)).ok()
+}
+
+impl PilDocument {
+    /// Renders the linked PIL statement by statement, recording where each one
+    /// came from. Statements the linker creates without a source are not mapped.
+    pub fn render(pil: &PILFile, asm_uri: &Url, asm_text: &str) -> Option<Self> {
+        let mut text = String::new();
+        let mut source_map = Vec::new();
+        let mut line = 0u32;
+
+        for statement in &pil.0 {
+            let rendered = statement.to_string();
+            let lines: Vec<&str> = rendered.lines().collect();
+            let end_line = line + lines.len().max(1) as u32 - 1;
+
+            let source = statement.source_reference();
+            if source.file_name.is_some() || source.start != source.end {
+                if let Some(location) =
+                    source_location(source, source.start..source.end, asm_uri, asm_text)
+                {
+                    let end_character = lines.last().map_or(0, |l| l.len() as u32);
+                    source_map.push(SourceMapping {
+                        pil_range: Range::new(
+                            Position::new(line, 0),
+                            Position::new(end_line, end_character),
+                        ),
+                        source: location,
+                    });
+                }
+            }
+
+            text.push_str(&rendered);
+            text.push('\n');
+            line = end_line + 1;
+        }
+
+        Some(Self {
+            uri: pil_uri(asm_uri)?,
+            text,
+            source_map,
+        })
+    }
+
+    pub fn source_at(&self, position: Position) -> Option<Location> {
+        self.source_map
+            .iter()
+            .find(|mapping| {
+                mapping.pil_range.start.line <= position.line
+                    && position.line <= mapping.pil_range.end.line
+            })
+            .map(|mapping| mapping.source.clone())
+    }
+}
@@ -0,0 +1,67 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use tower_lsp::Client;
+use tower_lsp::lsp_types::notification::Progress;
+use tower_lsp::lsp_types::request::WorkDoneProgressCreate;
+use tower_lsp::lsp_types::*;
+
+static NEXT_TOKEN: AtomicU32 = AtomicU32::new(0);
+
+/// A `window/workDoneProgress` created by the server.
+pub struct ProgressReporter<'a> {
+    client: &'a Client,
+    token: NumberOrString,
+}
+
+impl<'a> ProgressReporter<'a> {
+    /// Asks the client to create the progress and sends the `begin` report.
+    /// Returns `None` if the client refuses it.
+    pub async fn begin(client: &'a Client, title: &str) -> Option<Self> {
+        // Several progresses can run at once, e.g. when workspace folders are added.
+        let id = NEXT_TOKEN.fetch_add(1, Ordering::Relaxed);
+        let token = NumberOrString::String(format!("powdr/progress/{id}"));
+        client
+            .send_request::<WorkDoneProgressCreate>(WorkDoneProgressCreateParams {
+                token: token.clone(),
+            })
+            .await
+            .ok()?;
+
+        let progress = Self { client, token };
+        progress
+            .send(WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title: title.to_string(),
+                cancellable: Some(false),
+                message: None,
+                percentage: Some(0),
+            }))
+            .await;
+        Some(progress)
+    }
+
+    pub async fn report(&self, done: usize, total: usize) {
+        let percentage = (done * 100).checked_div(total).unwrap_or(100) as u32;
+        self.send(WorkDoneProgress::Report(WorkDoneProgressReport {
+            cancellable: Some(false),
+            message: Some(format!("{done}/{total}")),
+            percentage: Some(percentage),
+        }))
+        .await;
+    }
+
+    pub async fn end(self, message: String) {
+        self.send(WorkDoneProgress::End(WorkDoneProgressEnd {
+            message: Some(message),
+        }))
+        .await;
+    }
+
+    async fn send(&self, value: WorkDoneProgress) {
+        self.client
+            .send_notification::<Progress>(ProgressParams {
+                token: self.token.clone(),
+                value: ProgressParamsValue::WorkDone(value),
+            })
+            .await;
+    }
+}
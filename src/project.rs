@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use tower_lsp::lsp_types::Url;
+
+use crate::call_hierarchy::{CallGraph, build_call_graph};
+use crate::parser::AnalyzedDoc;
+use crate::symbol::{SemanticIndex, SymbolKind};
+
+/// A snapshot of an analyzed document. Cloning it is cheap. The AST is not
+/// kept here: the query database holds it for open documents, and workspace
+/// files only need what is derived from it.
+#[derive(Debug, Clone)]
+pub struct ParsedDocument {
+    pub text: Arc<str>,
+    pub version: i32,
+    pub semantic_index: Arc<SemanticIndex>,
+    /// Only asm documents have one.
+    pub call_graph: Option<Arc<CallGraph>>,
+}
+
+impl ParsedDocument {
+    pub fn new<T>(
+        uri: &Url,
+        text: Arc<str>,
+        version: i32,
+        analyzed: &AnalyzedDoc<T>,
+        semantic_index: Arc<SemanticIndex>,
+    ) -> Self {
+        let call_graph = match analyzed {
+            AnalyzedDoc::ASM(asm) => Some(Arc::new(build_call_graph(uri, &text, asm))),
+            AnalyzedDoc::PIL(_) => None,
+        };
+        Self {
+            text,
+            version,
+            semantic_index,
+            call_graph,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ProjectCache {
+    pub documents: HashMap<Url, ParsedDocument>,
+    /// Files known only from the on-disk index cache, they have no AST until
+    /// they are opened or change.
+    pub cached_indexes: HashMap<Url, SemanticIndex>,
+    pub symbol_locations: HashMap<String, Vec<(Url, SymbolKind)>>,
+    /// Incremented on every change, so that derived data can tell it is stale.
+    pub revision: u64,
+}
+
+impl Default for ProjectCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProjectCache {
+    pub fn new() -> Self {
+        Self {
+            documents: HashMap::new(),
+            cached_indexes: HashMap::new(),
+            symbol_locations: HashMap::new(),
+            revision: 0,
+        }
+    }
+
+    pub fn update_document(&mut self, uri: Url, doc: ParsedDocument) {
+        self.revision += 1;
+        self.remove_document_symbols(&uri);
+        self.cached_indexes.remove(&uri);
+
+        // Actualizar symbol_locations basado en el nuevo semantic_index
+        self.add_symbol_locations(&uri, &doc.semantic_index);
+
+        self.documents.insert(uri, doc);
+    }
+
+    pub fn update_cached_index(&mut self, uri: Url, index: SemanticIndex) {
+        self.revision += 1;
+        self.remove_document_symbols(&uri);
+        self.add_symbol_locations(&uri, &index);
+        self.cached_indexes.insert(uri, index);
+    }
+
+    fn add_symbol_locations(&mut self, uri: &Url, index: &SemanticIndex) {
+        for (_, symbol) in index.symbols.iter() {
+            self.symbol_locations
+                .entry(symbol.name.clone())
+                .or_default()
+                .push((uri.clone(), symbol.kind.clone()));
+        }
+    }
+
+    pub fn remove_document_symbols(&mut self, uri: &Url) {
+        for locations in self.symbol_locations.values_mut() {
+            locations.retain(|(doc_uri, _)| doc_uri != uri);
+        }
+
+        self.symbol_locations
+            .retain(|_, locations| !locations.is_empty());
+    }
+
+    /// Drops every document and cached index for which `remove` returns true.
+    pub fn remove_documents(&mut self, remove: impl Fn(&Url) -> bool) {
+        let uris: Vec<Url> = self
+            .documents
+            .keys()
+            .chain(self.cached_indexes.keys())
+            .filter(|uri| remove(uri))
+            .cloned()
+            .collect();
+
+        for uri in uris {
+            self.revision += 1;
+            self.remove_document_symbols(&uri);
+            self.documents.remove(&uri);
+            self.cached_indexes.remove(&uri);
+        }
+    }
+
+    /// Every indexed document with its index, whether analyzed or loaded from the
+    /// on-disk cache. Text is only available for analyzed documents.
+    pub fn indexes(&self) -> impl Iterator<Item = (&Url, Option<&Arc<str>>, &SemanticIndex)> {
+        self.documents
+            .iter()
+            .map(|(uri, doc)| (uri, Some(&doc.text), &*doc.semantic_index))
+            .chain(
+                self.cached_indexes
+                    .iter()
+                    .map(|(uri, index)| (uri, None, index)),
+            )
+    }
+
+    pub fn call_graphs(&self) -> impl Iterator<Item = &Arc<CallGraph>> {
+        self.documents
+            .values()
+            .filter_map(|doc| doc.call_graph.as_ref())
+    }
+
+    pub fn get_symbol_locations(&self, name: &str) -> Vec<(Url, SymbolKind)> {
+        self.symbol_locations.get(name).cloned().unwrap_or_default()
+    }
+}
@@ -0,0 +1,751 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use powdr_ast::parsed::SourceReference;
+use powdr_ast::parsed::asm::{ASMModule, ASMProgram, Module, ModuleStatement, SymbolValue};
+use powdr_number::{BigInt, FieldElement};
+use tower_lsp::lsp_types::{Diagnostic, Url};
+use tracing::debug;
+
+use crate::analyzer::{MachineUnits, build_semantic_index_reusing};
+use crate::compile::Lowered;
+use crate::lints::lint;
+use crate::parser::{
+    AnalyzedDoc, Error, ParsedAst, analyze_asm, analyze_pil, parse_ast, resolve_imports,
+    to_diagnostics,
+};
+use crate::project::ParsedDocument;
+use crate::scheduler::Cancellation;
+use crate::symbol::SemanticIndex;
+
+pub type Revision = u64;
+
+/// The stages of the analysis pipeline. Each one is memoized per document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Query {
+    Parse,
+    ResolveImports,
+    Analyze,
+    Index,
+    Lint,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Dependency {
+    /// The text of the document. Only parsing reads it, later stages get it
+    /// from the parse.
+    Text,
+    /// An earlier stage for the same document.
+    Query(Query),
+    /// A file the importer read from disk, with its modification time.
+    File(PathBuf, Option<SystemTime>),
+}
+
+struct Input {
+    text: Arc<str>,
+    changed_at: Revision,
+}
+
+struct Memo<V> {
+    value: Arc<V>,
+    /// The revision in which the value last changed.
+    changed_at: Revision,
+    /// The last revision in which the dependencies were checked.
+    verified_at: Revision,
+    dependencies: Vec<Dependency>,
+}
+
+type Table<V> = HashMap<Url, Memo<V>>;
+
+/// What `Published::lowering` knows about an asm document's PIL.
+pub enum Lowering<T> {
+    /// Lowered from the published analysis.
+    Done(Arc<Lowered<T>>),
+    /// The analysis of `text` is not lowered yet. The caller lowers it without
+    /// holding the lock and stores it with `set_lowered`.
+    Pending {
+        text: Arc<str>,
+        analyzed: Arc<AnalyzedDoc<T>>,
+    },
+}
+
+struct Parsed {
+    text: Arc<str>,
+    ast: Result<ParsedAst, Vec<Error>>,
+}
+
+struct Analysis<T> {
+    /// The text that was analyzed.
+    text: Arc<str>,
+    analyzed: Arc<AnalyzedDoc<T>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Tells whether a recomputed value equals the memoized one. If it does, the
+/// memo keeps the revision it last changed in and the stages depending on it
+/// are not recomputed, e.g. a comment edit changes the parse but neither the
+/// analysis nor the lowered PIL.
+trait Backdate {
+    fn same(&self, other: &Self) -> bool;
+}
+
+impl Backdate for Parsed {
+    fn same(&self, other: &Self) -> bool {
+        self.text == other.text
+    }
+}
+
+impl Backdate for Result<ASMProgram, Vec<Error>> {
+    fn same(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Ok(program), Ok(other)) => program.to_string() == other.to_string(),
+            (Err(errors), Err(other)) => errors == other,
+            _ => false,
+        }
+    }
+}
+
+impl<T: FieldElement> Backdate for Analysis<T> {
+    fn same(&self, other: &Self) -> bool {
+        self.diagnostics == other.diagnostics
+            && match (self.analyzed.as_ref(), other.analyzed.as_ref()) {
+                (AnalyzedDoc::ASM(asm), AnalyzedDoc::ASM(other)) => {
+                    asm.to_string() == other.to_string()
+                }
+                (AnalyzedDoc::PIL(pil), AnalyzedDoc::PIL(other)) => {
+                    pil.to_string() == other.to_string()
+                }
+                _ => false,
+            }
+    }
+}
+
+impl Backdate for SemanticIndex {
+    fn same(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+impl Backdate for Vec<Diagnostic> {
+    fn same(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+/// Memoizes the pipeline parse → import resolution → analysis → index, and
+/// the lints, in the style of salsa. Setting a document's text starts a new
+/// revision. A memoized stage is reused as long as none of its dependencies
+/// changed since it was last verified, so re-analyzing an unchanged document,
+/// or one whose edit did not reach a stage, does no work.
+///
+/// powdr parses and analyzes a file as a whole. The index is built per
+/// machine, an edit to one machine re-indexes only that machine.
+pub struct QueryDatabase<T> {
+    revision: Revision,
+    inputs: HashMap<Url, Input>,
+    parsed: Table<Parsed>,
+    resolved: Table<Result<ASMProgram, Vec<Error>>>,
+    analyzed: Table<Analysis<T>>,
+    indexes: Table<SemanticIndex>,
+    lints: Table<Vec<Diagnostic>>,
+    /// Index fragments of the machines of each document.
+    units: HashMap<Url, MachineUnits>,
+}
+
+impl<T> fmt::Debug for QueryDatabase<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QueryDatabase")
+            .field("revision", &self.revision)
+            .field("documents", &self.inputs.len())
+            .finish()
+    }
+}
+
+impl<T> Default for QueryDatabase<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> QueryDatabase<T> {
+    pub fn new() -> Self {
+        Self {
+            revision: 0,
+            inputs: HashMap::new(),
+            parsed: HashMap::new(),
+            resolved: HashMap::new(),
+            analyzed: HashMap::new(),
+            indexes: HashMap::new(),
+            lints: HashMap::new(),
+            units: HashMap::new(),
+        }
+    }
+
+    /// Sets the text of a document. Unchanged text does not start a new revision.
+    pub fn set_text(&mut self, uri: &Url, text: Arc<str>) {
+        if self.inputs.get(uri).is_some_and(|input| input.text == text) {
+            return;
+        }
+
+        self.revision += 1;
+        self.inputs.insert(
+            uri.clone(),
+            Input {
+                text,
+                changed_at: self.revision,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, uri: &Url) {
+        self.revision += 1;
+        self.inputs.remove(uri);
+        self.parsed.remove(uri);
+        self.resolved.remove(uri);
+        self.analyzed.remove(uri);
+        self.indexes.remove(uri);
+        self.lints.remove(uri);
+        self.units.remove(uri);
+    }
+
+    pub fn text(&self, uri: &Url) -> Option<Arc<str>> {
+        self.inputs.get(uri).map(|input| input.text.clone())
+    }
+
+    /// The last analysis of a document, with the text it analyzed. Nothing is
+    /// computed, documents are analyzed by `document`.
+    pub fn analyzed(&self, uri: &Url) -> Option<(Arc<str>, Arc<AnalyzedDoc<T>>)> {
+        let analysis = &self.analyzed.get(uri)?.value;
+        Some((analysis.text.clone(), analysis.analyzed.clone()))
+    }
+}
+
+impl<T: FieldElement> QueryDatabase<T> {
+    /// Runs the pipeline for a document whose text was set before. Returns
+    /// `None` as soon as the analysis is cancelled, stages finished before
+    /// that stay memoized.
+    pub fn document(
+        &mut self,
+        uri: &Url,
+        version: i32,
+        cancellation: &Cancellation,
+    ) -> Option<(ParsedDocument, Vec<Diagnostic>)> {
+        self.text(uri)?;
+        self.parsed(uri);
+        if cancellation.is_cancelled() {
+            return None;
+        }
+        let analysis = self.analysis(uri);
+        if cancellation.is_cancelled() {
+            return None;
+        }
+        let semantic_index = self.semantic_index(uri);
+        if cancellation.is_cancelled() {
+            return None;
+        }
+        let lints = self.lints(uri);
+
+        Some((
+            ParsedDocument::new(
+                uri,
+                analysis.text.clone(),
+                version,
+                &analysis.analyzed,
+                semantic_index,
+            ),
+            analysis
+                .diagnostics
+                .iter()
+                .chain(lints.iter())
+                .cloned()
+                .collect(),
+        ))
+    }
+
+    fn parsed(&mut self, uri: &Url) -> Arc<Parsed> {
+        self.fetch(
+            uri,
+            Query::Parse,
+            |db| &mut db.parsed,
+            |db, uri| {
+                let text = db.inputs[uri].text.clone();
+                let ast = parse_ast(&text, uri);
+                (Parsed { text, ast }, vec![Dependency::Text])
+            },
+        )
+    }
+
+    /// Only meaningful for asm documents that parsed successfully, errors of
+    /// earlier stages are reported by those.
+    fn resolved(&mut self, uri: &Url) -> Arc<Result<ASMProgram, Vec<Error>>> {
+        self.fetch(
+            uri,
+            Query::ResolveImports,
+            |db| &mut db.resolved,
+            |db, uri| {
+                let mut dependencies = vec![Dependency::Query(Query::Parse)];
+                let resolved = match &db.parsed(uri).ast {
+                    Ok(ParsedAst::ASM(program)) => resolve_imports(uri, program.clone()),
+                    _ => Err(vec![]),
+                };
+
+                if let Ok(program) = &resolved {
+                    let mut files = BTreeSet::new();
+                    imported_files(&program.main, &mut files);
+                    dependencies.extend(files.into_iter().map(|path| {
+                        let modified = modified_time(&path);
+                        Dependency::File(path, modified)
+                    }));
+                }
+
+                (resolved, dependencies)
+            },
+        )
+    }
+
+    fn analysis(&mut self, uri: &Url) -> Arc<Analysis<T>> {
+        self.fetch(
+            uri,
+            Query::Analyze,
+            |db| &mut db.analyzed,
+            |db, uri| {
+                let parsed = db.parsed(uri);
+                let text = parsed.text.clone();
+                let mut dependencies = vec![Dependency::Query(Query::Parse)];
+
+                let result = match &parsed.ast {
+                    Err(errors) => Err(to_diagnostics(errors, &text)),
+                    Ok(ParsedAst::PIL(pil)) => analyze_pil::<T>(uri, pil.clone())
+                        .map(AnalyzedDoc::PIL)
+                        .map_err(|errors| to_diagnostics(&errors, &text)),
+                    Ok(ParsedAst::ASM(_)) => {
+                        dependencies.push(Dependency::Query(Query::ResolveImports));
+                        match &*db.resolved(uri) {
+                            Err(errors) => Err(to_diagnostics(errors, &text)),
+                            Ok(program) => analyze_asm(uri, program.clone())
+                                .map(AnalyzedDoc::ASM)
+                                .map_err(|errors| to_diagnostics(&errors, &text)),
+                        }
+                    }
+                };
+
+                let analysis = match result {
+                    Ok(analyzed) => Analysis {
+                        text,
+                        analyzed: Arc::new(analyzed),
+                        diagnostics: vec![],
+                    },
+                    Err(diagnostics) => Analysis {
+                        text,
+                        // Default in case of error
+                        analyzed: Arc::new(AnalyzedDoc::ASM(Default::default())),
+                        diagnostics,
+                    },
+                };
+                (analysis, dependencies)
+            },
+        )
+    }
+
+    fn semantic_index(&mut self, uri: &Url) -> Arc<SemanticIndex> {
+        self.fetch(
+            uri,
+            Query::Index,
+            |db| &mut db.indexes,
+            |db, uri| {
+                let parsed = db.parsed(uri);
+                let analysis = db.analysis(uri);
+                let mut units = db.units.remove(uri).unwrap_or_default();
+                let index = build_semantic_index_reusing(
+                    &analysis.analyzed,
+                    parsed.ast.as_ref().ok(),
+                    &parsed.text,
+                    &mut units,
+                );
+                db.units.insert(uri.clone(), units);
+                (
+                    index,
+                    vec![
+                        Dependency::Query(Query::Parse),
+                        Dependency::Query(Query::Analyze),
+                    ],
+                )
+            },
+        )
+    }
+
+    fn lints(&mut self, uri: &Url) -> Arc<Vec<Diagnostic>> {
+        self.fetch(
+            uri,
+            Query::Lint,
+            |db| &mut db.lints,
+            |db, uri| {
+                let parsed = db.parsed(uri);
+                let analysis = db.analysis(uri);
+                let index = db.semantic_index(uri);
+                (
+                    lint(&parsed.text, &analysis.analyzed, &index),
+                    vec![
+                        Dependency::Query(Query::Parse),
+                        Dependency::Query(Query::Analyze),
+                        Dependency::Query(Query::Index),
+                    ],
+                )
+            },
+        )
+    }
+
+    fn fetch<V: Backdate>(
+        &mut self,
+        uri: &Url,
+        query: Query,
+        table: fn(&mut Self) -> &mut Table<V>,
+        compute: fn(&mut Self, &Url) -> (V, Vec<Dependency>),
+    ) -> Arc<V> {
+        let revision = self.revision;
+
+        if let Some(memo) = table(self).get(uri) {
+            if memo.verified_at == revision {
+                return memo.value.clone();
+            }
+
+            let (dependencies, verified_at) = (memo.dependencies.clone(), memo.verified_at);
+            if self.unchanged_since(uri, &dependencies, verified_at) {
+                let memo = table(self).get_mut(uri).unwrap();
+                memo.verified_at = revision;
+                return memo.value.clone();
+            }
+        }
+
+        debug!(%uri, ?query, revision, "recomputing query");
+        let (value, dependencies) = compute(self, uri);
+        // The new value is kept even when backdated: equal values can still
+        // differ in source positions, which stages depending on the text
+        // through the parse pick up from it.
+        let changed_at = match table(self).get(uri) {
+            Some(memo) if memo.value.same(&value) => memo.changed_at,
+            _ => revision,
+        };
+        let value = Arc::new(value);
+        table(self).insert(
+            uri.clone(),
+            Memo {
+                value: value.clone(),
+                changed_at,
+                verified_at: revision,
+                dependencies,
+            },
+        );
+        value
+    }
+
+    fn unchanged_since(
+        &mut self,
+        uri: &Url,
+        dependencies: &[Dependency],
+        revision: Revision,
+    ) -> bool {
+        dependencies.iter().all(|dependency| match dependency {
+            Dependency::Text => self
+                .inputs
+                .get(uri)
+                .is_some_and(|input| input.changed_at <= revision),
+            Dependency::Query(query) => self.changed_at(uri, *query) <= revision,
+            Dependency::File(path, modified) => modified_time(path) == *modified,
+        })
+    }
+
+    /// Brings the stage up to date and returns when its value last changed.
+    fn changed_at(&mut self, uri: &Url, query: Query) -> Revision {
+        match query {
+            Query::Parse => {
+                self.parsed(uri);
+                self.parsed[uri].changed_at
+            }
+            Query::ResolveImports => {
+                self.resolved(uri);
+                self.resolved[uri].changed_at
+            }
+            Query::Analyze => {
+                self.analysis(uri);
+                self.analyzed[uri].changed_at
+            }
+            Query::Index => {
+                self.semantic_index(uri);
+                self.indexes[uri].changed_at
+            }
+            Query::Lint => {
+                self.lints(uri);
+                self.lints[uri].changed_at
+            }
+        }
+    }
+}
+
+/// The analyses last published to the client, with the PIL lowered from them.
+/// Requests are answered from here rather than from the `QueryDatabase`,
+/// which stays locked while an edit runs through the pipeline.
+pub struct Published<T> {
+    documents: HashMap<Url, PublishedDocument<T>>,
+}
+
+/// Values of the constants of a published analysis, by name, as far as
+/// hovers evaluated them.
+pub type ConstantValues = Mutex<HashMap<String, Option<BigInt>>>;
+
+struct PublishedDocument<T> {
+    text: Arc<str>,
+    analyzed: Arc<AnalyzedDoc<T>>,
+    lowered: Option<Arc<Lowered<T>>>,
+    constants: Arc<ConstantValues>,
+}
+
+impl<T> Default for Published<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Published<T> {
+    pub fn new() -> Self {
+        Self {
+            documents: HashMap::new(),
+        }
+    }
+
+    /// Publishes the analysis of `text`. Its lowered PIL is kept only if the
+    /// text is unchanged: an equal analysis of another text, e.g. after a
+    /// comment edit, still has other source positions.
+    pub fn publish(&mut self, uri: &Url, text: Arc<str>, analyzed: Arc<AnalyzedDoc<T>>) {
+        let lowered = self
+            .documents
+            .remove(uri)
+            .filter(|document| document.text == text)
+            .and_then(|document| document.lowered);
+        self.documents.insert(
+            uri.clone(),
+            PublishedDocument {
+                text,
+                analyzed,
+                lowered,
+                constants: Default::default(),
+            },
+        );
+    }
+
+    pub fn remove(&mut self, uri: &Url) {
+        self.documents.remove(uri);
+    }
+
+    /// The published analysis of a document, with the text it analyzed.
+    pub fn analyzed(&self, uri: &Url) -> Option<(Arc<str>, Arc<AnalyzedDoc<T>>)> {
+        let document = self.documents.get(uri)?;
+        Some((document.text.clone(), document.analyzed.clone()))
+    }
+
+    /// The constants of the published analysis of a document evaluated so far.
+    pub fn constant_values(&self, uri: &Url) -> Option<Arc<ConstantValues>> {
+        Some(self.documents.get(uri)?.constants.clone())
+    }
+
+    /// The lowered PIL of the published analysis of an asm document. Lowering
+    /// is slow, so it is not computed here, under the lock, but handed out as
+    /// pending.
+    pub fn lowering(&self, uri: &Url) -> Option<Lowering<T>> {
+        let document = self.documents.get(uri)?;
+        if !matches!(*document.analyzed, AnalyzedDoc::ASM(_)) {
+            return None;
+        }
+
+        match &document.lowered {
+            Some(lowered) => Some(Lowering::Done(lowered.clone())),
+            None => Some(Lowering::Pending {
+                text: document.text.clone(),
+                analyzed: document.analyzed.clone(),
+            }),
+        }
+    }
+
+    /// Keeps the PIL lowered from the analysis of `text`, unless another text
+    /// was published in the meantime. Failures are kept too, so that a
+    /// lowering that timed out is not retried before the document changes.
+    pub fn set_lowered(&mut self, uri: &Url, text: &str, lowered: Arc<Lowered<T>>) {
+        if let Some(document) = self
+            .documents
+            .get_mut(uri)
+            .filter(|document| &*document.text == text)
+        {
+            document.lowered = Some(lowered);
+        }
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Collects the files that statements of the resolved program come from.
+fn imported_files(module: &ASMModule, files: &mut BTreeSet<PathBuf>) {
+    for statement in &module.statements {
+        let ModuleStatement::SymbolDefinition(definition) = statement else {
+            continue;
+        };
+
+        match &definition.value {
+            SymbolValue::Machine(machine) => {
+                files.extend(machine.statements.iter().filter_map(|statement| {
+                    statement
+                        .source_reference()
+                        .file_name
+                        .as_deref()
+                        .map(PathBuf::from)
+                }))
+            }
+            SymbolValue::Module(Module::Local(inner)) => imported_files(inner, files),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use powdr_number::GoldilocksField;
+
+    const MACHINES: &str = "\
+machine Main with degree: 8 {
+    reg pc[@pc];
+    reg A;
+}
+
+machine Other with degree: 8 {
+    reg pc[@pc];
+    reg B;
+}
+";
+
+    fn analyze(db: &mut QueryDatabase<GoldilocksField>, uri: &Url, text: &str) {
+        db.set_text(uri, text.into());
+        db.document(uri, 0, &Cancellation::default()).unwrap();
+    }
+
+    fn uri() -> Url {
+        Url::parse("file:///test.asm").unwrap()
+    }
+
+    #[test]
+    fn unchanged_text_reuses_every_stage() {
+        let (mut db, uri) = (QueryDatabase::new(), uri());
+        analyze(&mut db, &uri, MACHINES);
+        let index = db.indexes[&uri].value.clone();
+
+        analyze(&mut db, &uri, MACHINES);
+        assert!(Arc::ptr_eq(&index, &db.indexes[&uri].value));
+    }
+
+    #[test]
+    fn comment_edit_keeps_the_analysis() {
+        let (mut db, uri) = (QueryDatabase::new(), uri());
+        analyze(&mut db, &uri, MACHINES);
+        let analyzed_at = db.analyzed[&uri].changed_at;
+
+        analyze(&mut db, &uri, &format!("{MACHINES}// the end\n"));
+        assert_eq!(db.parsed[&uri].changed_at, db.revision);
+        assert_eq!(db.analyzed[&uri].changed_at, analyzed_at);
+    }
+
+    #[test]
+    fn edit_invalidates_later_stages() {
+        let (mut db, uri) = (QueryDatabase::new(), uri());
+        analyze(&mut db, &uri, MACHINES);
+
+        analyze(
+            &mut db,
+            &uri,
+            &MACHINES.replace("reg B;", "reg B;\n    reg C;"),
+        );
+        assert_eq!(db.analyzed[&uri].changed_at, db.revision);
+        assert!(
+            db.indexes[&uri]
+                .value
+                .symbols
+                .values()
+                .any(|symbol| symbol.name == "C")
+        );
+    }
+
+    #[test]
+    fn edit_to_one_machine_keeps_the_unit_of_another() {
+        let (mut db, uri) = (QueryDatabase::new(), uri());
+        analyze(&mut db, &uri, MACHINES);
+        let main_uses = |db: &QueryDatabase<GoldilocksField>| {
+            db.indexes[&uri]
+                .value
+                .uses
+                .iter()
+                .filter(|symbol_use| symbol_use.scope == "::Main")
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        let before = main_uses(&db);
+
+        analyze(&mut db, &uri, &MACHINES.replace("reg B;", "reg C;"));
+        assert_eq!(main_uses(&db), before);
+        assert!(db.units[&uri].reused("::Main"));
+        assert!(!db.units[&uri].reused("::Other"));
+    }
+
+    fn publish(
+        db: &QueryDatabase<GoldilocksField>,
+        published: &mut Published<GoldilocksField>,
+        uri: &Url,
+    ) -> Arc<str> {
+        let (text, analyzed) = db.analyzed(uri).unwrap();
+        published.publish(uri, text.clone(), analyzed);
+        text
+    }
+
+    #[test]
+    fn comment_edit_lowers_again() {
+        let (mut db, uri) = (QueryDatabase::new(), uri());
+        let mut published = Published::new();
+        analyze(&mut db, &uri, MACHINES);
+        let text = publish(&db, &mut published, &uri);
+        published.set_lowered(&uri, &text, Arc::new(Err(vec![])));
+
+        publish(&db, &mut published, &uri);
+        assert!(matches!(published.lowering(&uri), Some(Lowering::Done(_))));
+
+        let edited = format!("{MACHINES}// the end\n");
+        analyze(&mut db, &uri, &edited);
+        publish(&db, &mut published, &uri);
+        match published.lowering(&uri) {
+            Some(Lowering::Pending { text, .. }) => assert_eq!(&*text, edited),
+            _ => panic!("the lowering of the old text was kept"),
+        }
+
+        // The lowering of the old text finishing late is dropped.
+        published.set_lowered(&uri, &text, Arc::new(Err(vec![])));
+        assert!(matches!(
+            published.lowering(&uri),
+            Some(Lowering::Pending { .. })
+        ));
+    }
+
+    #[test]
+    fn removed_document_is_forgotten() {
+        let (mut db, uri) = (QueryDatabase::new(), uri());
+        analyze(&mut db, &uri, MACHINES);
+
+        db.remove(&uri);
+        assert!(db.text(&uri).is_none());
+        assert!(db.analyzed(&uri).is_none());
+        assert!(!db.units.contains_key(&uri));
+    }
+}
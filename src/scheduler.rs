@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+use tower_lsp::lsp_types::Url;
+
+/// How long to wait for further edits before analyzing a changed document.
+pub const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Tells an analysis that it was superseded. Aborting its task only drops the
+/// future, work already handed to a blocking thread keeps running, so that
+/// work checks this between its stages and stops early.
+#[derive(Debug, Clone, Default)]
+pub struct Cancellation(Arc<AtomicBool>);
+
+impl Cancellation {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Runs document analyses on background tasks. There is at most one pending
+/// analysis per document: scheduling a new one aborts and cancels the
+/// previous, whether it is still waiting out the debounce or already running.
+#[derive(Debug, Default)]
+pub struct AnalysisScheduler {
+    pending: Mutex<HashMap<Url, (JoinHandle<()>, Cancellation)>>,
+}
+
+impl AnalysisScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn schedule<F>(&self, uri: Url, delay: Duration, analysis: impl FnOnce(Cancellation) -> F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let cancellation = Cancellation::default();
+        let analysis = analysis(cancellation.clone());
+        let task = tokio::spawn(async move {
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+            analysis.await;
+        });
+
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, (task, _)| !task.is_finished());
+        if let Some((previous, cancellation)) = pending.insert(uri, (task, cancellation)) {
+            previous.abort();
+            cancellation.cancel();
+        }
+    }
+
+    /// Aborts and cancels the pending analysis of a document, e.g. one that
+    /// was closed.
+    pub fn cancel(&self, uri: &Url) {
+        if let Some((task, cancellation)) = self.pending.lock().unwrap().remove(uri) {
+            task.abort();
+            cancellation.cancel();
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use crate::parser::position_to_offset;
+use crate::span::{Span, code_tokens};
+use crate::symbol::{SemanticIndex, Symbol, SymbolDetails, SymbolId, SymbolKind};
+use tower_lsp::lsp_types::*;
+
+pub struct SignatureHelpProvider<'a> {
+    text: &'a str,
+    semantic_index: &'a SemanticIndex,
+}
+
+/// The call around a position: where its callee is written and which of its
+/// arguments the position is in.
+struct Call {
+    callee: Span,
+    argument: u32,
+}
+
+impl<'a> SignatureHelpProvider<'a> {
+    pub fn new(text: &'a str, semantic_index: &'a SemanticIndex) -> Self {
+        Self {
+            text,
+            semantic_index,
+        }
+    }
+
+    /// The signature and doc comment of the function, operation or
+    /// instruction called around `position`.
+    pub fn get_signature_help(&self, position: Position) -> Option<SignatureHelp> {
+        let offset = position_to_offset(position, self.text)?;
+        let call = self.call_at(offset)?;
+        let id = self.callee(&call.callee)?;
+        let symbol = &self.semantic_index.symbols[&id];
+        let (label, parameters) = signature(symbol)?;
+
+        let parameters: Vec<_> = parameters
+            .into_iter()
+            .map(|span| ParameterInformation {
+                label: ParameterLabel::LabelOffsets([
+                    utf16_len(&label[..span.start]),
+                    utf16_len(&label[..span.end]),
+                ]),
+                documentation: None,
+            })
+            .collect();
+        let active_parameter =
+            (call.argument as usize).min(parameters.len().saturating_sub(1)) as u32;
+
+        Some(SignatureHelp {
+            signatures: vec![SignatureInformation {
+                label,
+                documentation: self.semantic_index.doc(id).map(|doc| {
+                    Documentation::MarkupContent(MarkupContent {
+                        kind: MarkupKind::Markdown,
+                        value: doc.to_string(),
+                    })
+                }),
+                parameters: Some(parameters),
+                active_parameter: Some(active_parameter),
+            }],
+            active_signature: Some(0),
+            active_parameter: Some(active_parameter),
+        })
+    }
+
+    /// Walks back from `offset` to the innermost parenthesis still open there.
+    fn call_at(&self, offset: usize) -> Option<Call> {
+        let tokens = code_tokens(self.text);
+        let before = tokens.partition_point(|token| token.end <= offset);
+        let mut depth = 0usize;
+        let mut argument = 0;
+
+        for (i, token) in tokens[..before].iter().enumerate().rev() {
+            match &self.text[token.clone()] {
+                ")" | "]" | "}" => depth += 1,
+                "(" if depth == 0 => {
+                    let callee = tokens[..i].last()?.clone();
+                    if self.text[callee.clone()]
+                        .starts_with(|c: char| c.is_alphabetic() || c == '_')
+                    {
+                        return Some(Call { callee, argument });
+                    }
+                    // Parentheses around an expression, the commas so far were inside.
+                    argument = 0;
+                }
+                "(" | "[" | "{" if depth > 0 => depth -= 1,
+                // A statement or block boundary, the position is not in a call.
+                ";" | "{" | "[" => return None,
+                "," if depth == 0 => argument += 1,
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// The declaration of the callable written at `callee`, resolved through
+    /// the index, or else the only callable declared under that name.
+    fn callee(&self, callee: &Span) -> Option<SymbolId> {
+        let index = self.semantic_index;
+        if let Some(id) = index.find_symbol_id_at_position(callee.start) {
+            return Some(index.declaration_of(id));
+        }
+
+        let name = &self.text[callee.clone()];
+        let mut candidates = index.symbols.iter().filter(|(id, symbol)| {
+            index.declaration_of(**id) == **id
+                && is_callable(&symbol.kind)
+                && symbol.name.rsplit("::").next() == Some(name)
+        });
+        let (id, _) = candidates.next()?;
+        candidates.next().is_none().then_some(*id)
+    }
+}
+
+fn is_callable(kind: &SymbolKind) -> bool {
+    matches!(
+        kind,
+        SymbolKind::Function | SymbolKind::Instruction | SymbolKind::Operation
+    )
+}
+
+/// The label of the signature of `symbol` and the spans of its parameters in it.
+fn signature(symbol: &Symbol) -> Option<(String, Vec<Span>)> {
+    if !is_callable(&symbol.kind) {
+        return None;
+    }
+    match &symbol.details {
+        SymbolDetails::Callable { inputs, outputs } => {
+            let name = symbol.name.rsplit("::").next().unwrap_or(&symbol.name);
+            let mut label = format!("{name}(");
+            let parameters = split_list(inputs)
+                .map(|span| label.len() + span.start..label.len() + span.end)
+                .collect();
+            label.push_str(inputs);
+            label.push(')');
+            if !outputs.is_empty() {
+                label.push_str(&format!(" -> {outputs}"));
+            }
+            Some((label, parameters))
+        }
+        // PIL functions are shown with their type, e.g. `let f: int, fe -> fe`,
+        // whose parameters are the types before the arrow.
+        SymbolDetails::Declaration {
+            declaration,
+            type_info: Some(type_info),
+            ..
+        } => {
+            let offset = declaration.rfind(type_info.as_str())?;
+            let inputs = top_level_arrow(type_info).map_or("", |arrow| &type_info[..arrow]);
+            let inputs = inputs.trim_end();
+            let parameters = split_list(inputs)
+                .map(|span| offset + span.start..offset + span.end)
+                .collect();
+            Some((declaration.clone(), parameters))
+        }
+        _ => None,
+    }
+}
+
+/// The spans of the comma separated items of `list`, ignoring nested commas.
+fn split_list(list: &str) -> impl Iterator<Item = Span> + '_ {
+    let mut items = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (pos, c) in list.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                items.push(start..pos);
+                start = pos + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(start..list.len());
+
+    items.into_iter().filter_map(move |span| {
+        let item = &list[span.clone()];
+        let start = span.start + (item.len() - item.trim_start().len());
+        let end = span.end - (item.len() - item.trim_end().len());
+        (start < end).then_some(start..end)
+    })
+}
+
+/// The position of the `->` of a function type outside of any parentheses.
+fn top_level_arrow(ty: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    for (pos, c) in ty.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = depth.saturating_sub(1),
+            '-' if depth == 0 && ty[pos..].starts_with("->") => return Some(pos),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn utf16_len(text: &str) -> u32 {
+    text.encode_utf16().count() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn help(text: &str, offset: usize, index: &SemanticIndex) -> Option<SignatureHelp> {
+        let position = crate::parser::convert_position(offset, text);
+        SignatureHelpProvider::new(text, index).get_signature_help(position)
+    }
+
+    fn parameter_labels(help: &SignatureHelp) -> Vec<&str> {
+        let signature = &help.signatures[0];
+        signature
+            .parameters
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|parameter| match parameter.label {
+                ParameterLabel::LabelOffsets([start, end]) => {
+                    &signature.label[start as usize..end as usize]
+                }
+                ParameterLabel::Simple(_) => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn operation_call_shows_signature_and_doc() {
+        let text = "operation add a, b -> c;\nA <== add(B, (C + 1), D);";
+        let mut index = SemanticIndex::new();
+        let add = index.add_symbol(Symbol {
+            kind: SymbolKind::Operation,
+            name: "add".to_string(),
+            span: 10..13,
+            details: SymbolDetails::Callable {
+                inputs: "a, b".to_string(),
+                outputs: "c".to_string(),
+            },
+        });
+        index.add_doc(add, "Adds.".to_string());
+
+        let call = text.rfind("add(").unwrap();
+        let help = help(text, call + "add(B, (C".len(), &index).unwrap();
+        assert_eq!(help.signatures[0].label, "add(a, b) -> c");
+        assert_eq!(parameter_labels(&help), vec!["a", "b"]);
+        assert_eq!(help.active_parameter, Some(1));
+        assert_eq!(
+            help.signatures[0].documentation,
+            Some(Documentation::MarkupContent(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: "Adds.".to_string(),
+            }))
+        );
+    }
+
+    #[test]
+    fn pil_function_parameters_are_its_argument_types() {
+        let text = "let f: int, (int -> fe) -> fe = |a, g| g(a);\nlet x = Main::f(1, ";
+        let mut index = SemanticIndex::new();
+        let f = index.add_symbol(Symbol {
+            kind: SymbolKind::Function,
+            name: "Main::f".to_string(),
+            span: 4..5,
+            details: SymbolDetails::Declaration {
+                declaration: "let f: int, (int -> fe) -> fe".to_string(),
+                type_info: Some("int, (int -> fe) -> fe".to_string()),
+                length: None,
+                stage: None,
+                value: None,
+            },
+        });
+        index.add_occurrence(
+            Symbol {
+                span: 59..60,
+                ..index.symbols[&f].clone()
+            },
+            f,
+        );
+
+        let help = help(text, text.len(), &index).unwrap();
+        assert_eq!(parameter_labels(&help), vec!["int", "(int -> fe)"]);
+        assert_eq!(help.active_parameter, Some(1));
+    }
+
+    #[test]
+    fn no_help_outside_of_calls() {
+        let index = SemanticIndex::new();
+        assert!(help("f(a); b", 6, &index).is_none());
+    }
+}
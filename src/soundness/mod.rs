@@ -0,0 +1,353 @@
+use std::collections::{BTreeMap, HashMap};
+
+use powdr_ast::analyzed::{
+    AlgebraicBinaryOperation, AlgebraicBinaryOperator, AlgebraicExpression,
+    AlgebraicUnaryOperation, Analyzed, Identity, PolynomialType, SymbolKind,
+};
+use powdr_ast::parsed::visitor::AllChildren;
+use tower_lsp::lsp_types::*;
+use tracing::debug;
+
+use crate::analyzer::{find_word, is_local};
+use crate::compile::Lowered;
+use crate::parser::{AnalyzedDoc, convert_position};
+use crate::span::Span;
+
+pub const UNDERCONSTRAINED_COLUMN: &str = "underconstrained-column";
+
+/// Intermediate columns referring to each other are followed this deep.
+const MAX_INTERMEDIATE_DEPTH: usize = 16;
+
+/// How the identities of a PIL file mention a witness column.
+#[derive(Debug, Default)]
+struct ColumnUsage {
+    /// In a polynomial identity with a term that is the column times a
+    /// constant, so the identity can be solved for it.
+    linear: bool,
+    /// In a polynomial identity only with a higher degree or a non-constant
+    /// coefficient, e.g. `x * (1 - x) = 0` or `sel * (x - y) = 0`.
+    nonlinear: bool,
+    /// On the left hand side of a lookup, which restricts it to a set of values.
+    lookup_input: bool,
+    /// On the right hand side of a lookup or permutation, which reads its
+    /// values without determining them.
+    looked_up: bool,
+    /// On the left hand side of a permutation or in a connection, so its
+    /// values are provided by another machine.
+    connected: bool,
+}
+
+/// Flags witness columns of a PIL document whose values the constraints do
+/// not determine. Asm documents are checked on the PIL lowered from them, see
+/// `lowered_underconstrained_columns`.
+pub fn underconstrained_columns<T>(text: &str, analyzed: &AnalyzedDoc<T>) -> Vec<Diagnostic> {
+    match analyzed {
+        // Columns no identity mentions are already reported as unused in PIL files.
+        AnalyzedDoc::PIL(pil) => check(text, pil, false),
+        AnalyzedDoc::ASM(_) => vec![],
+    }
+}
+
+/// Flags witness columns of an asm document whose values the constraints of
+/// the PIL lowered from it do not determine.
+pub fn lowered_underconstrained_columns<T>(text: &str, lowered: &Lowered<T>) -> Vec<Diagnostic> {
+    match lowered {
+        Ok(compiled) => check(text, &compiled.analyzed, true),
+        Err(errors) => {
+            debug!(?errors, "cannot lower to PIL for the soundness check");
+            vec![]
+        }
+    }
+}
+
+fn check<T>(text: &str, pil: &Analyzed<T>, report_unused: bool) -> Vec<Diagnostic> {
+    let usages = column_usages(pil);
+    let unused = ColumnUsage::default();
+    let mut flagged: BTreeMap<usize, Diagnostic> = BTreeMap::new();
+
+    for (name, (symbol, _)) in &pil.definitions {
+        if !matches!(symbol.kind, SymbolKind::Poly(PolynomialType::Committed))
+            || !is_local(&symbol.source, text)
+        {
+            continue;
+        }
+
+        let usage = usages.get(name.as_str()).unwrap_or(&unused);
+        let explanation = if usage.linear || usage.connected {
+            continue;
+        } else if usage.nonlinear {
+            "is not determined by the constraints: every polynomial identity mentioning it \
+             is nonlinear in it, so more than one value can satisfy them. Add an identity \
+             that defines it, or connect it to another machine"
+        } else if usage.lookup_input {
+            "only appears on the left hand side of lookups, which restrict it to a set of \
+             values without determining which one. The prover can choose any of them"
+        } else if usage.looked_up {
+            "only appears on the right hand side of lookups and permutations, which read \
+             its values without determining them. Add an identity that defines it"
+        } else if report_unused {
+            "appears in no identity, so the prover can assign it any value"
+        } else {
+            continue;
+        };
+
+        let local_name = name
+            .rsplit_once("::")
+            .map_or(name.as_str(), |(_, name)| name);
+        let declaration = &text[symbol.source.start..symbol.source.end];
+        let Some(pos) = find_word(declaration, local_name) else {
+            continue;
+        };
+        let span: Span = symbol.source.start + pos..symbol.source.start + pos + local_name.len();
+
+        // A column of a machine instantiated several times is reported once.
+        flagged.entry(span.start).or_insert_with(|| Diagnostic {
+            range: Range::new(
+                convert_position(span.start, text),
+                convert_position(span.end, text),
+            ),
+            severity: Some(DiagnosticSeverity::WARNING),
+            code: Some(NumberOrString::String(UNDERCONSTRAINED_COLUMN.to_string())),
+            message: format!("Witness column `{}` {}", name, explanation),
+            source: Some("powdr".to_string()),
+            ..Default::default()
+        });
+    }
+
+    flagged.into_values().collect()
+}
+
+fn column_usages<T>(pil: &Analyzed<T>) -> HashMap<&str, ColumnUsage> {
+    let mut usages: HashMap<&str, ColumnUsage> = HashMap::new();
+
+    for identity in &pil.identities {
+        match identity {
+            Identity::Polynomial(identity) => {
+                for ((column, _), degree) in degrees(&identity.expression, pil, 0) {
+                    let usage = usages.entry(column).or_default();
+                    if degree == 1 {
+                        usage.linear = true;
+                    } else {
+                        usage.nonlinear = true;
+                    }
+                }
+            }
+            Identity::Lookup(lookup) => {
+                // A lookup of several columns into fixed columns only, e.g. into
+                // the ROM of an asm machine, is a table of values per key.
+                let into_table = lookup.left.expressions.len() > 1
+                    && mentioned(pil, lookup.right.all_children()).is_empty();
+                for column in mentioned(pil, lookup.left.all_children()) {
+                    let usage = usages.entry(column).or_default();
+                    if into_table {
+                        usage.connected = true;
+                    } else {
+                        usage.lookup_input = true;
+                    }
+                }
+                for column in mentioned(pil, lookup.right.all_children()) {
+                    usages.entry(column).or_default().looked_up = true;
+                }
+            }
+            Identity::PhantomLookup(lookup) => {
+                for column in mentioned(pil, lookup.left.all_children()) {
+                    usages.entry(column).or_default().lookup_input = true;
+                }
+                for column in mentioned(pil, lookup.right.all_children()) {
+                    usages.entry(column).or_default().looked_up = true;
+                }
+            }
+            Identity::Permutation(permutation) => {
+                for column in mentioned(pil, permutation.left.all_children()) {
+                    usages.entry(column).or_default().connected = true;
+                }
+                for column in mentioned(pil, permutation.right.all_children()) {
+                    usages.entry(column).or_default().looked_up = true;
+                }
+            }
+            Identity::PhantomPermutation(permutation) => {
+                for column in mentioned(pil, permutation.left.all_children()) {
+                    usages.entry(column).or_default().connected = true;
+                }
+                for column in mentioned(pil, permutation.right.all_children()) {
+                    usages.entry(column).or_default().looked_up = true;
+                }
+            }
+            _ => {
+                for column in mentioned(pil, identity.all_children()) {
+                    usages.entry(column).or_default().connected = true;
+                }
+            }
+        }
+    }
+
+    usages
+}
+
+/// A witness column on the current row, or on the next one.
+type Column<'a> = (&'a str, bool);
+
+/// The witness columns the expressions refer to, directly or through
+/// intermediate columns.
+fn mentioned<'a, T: 'a>(
+    pil: &'a Analyzed<T>,
+    expressions: impl Iterator<Item = &'a AlgebraicExpression<T>>,
+) -> Vec<&'a str> {
+    expressions
+        .flat_map(|expr| degrees(expr, pil, 0).into_keys())
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// The degree of `expr` in each witness column it refers to, where 2 stands
+/// for any degree above one or a non-constant coefficient, i.e. for anything
+/// an identity cannot be solved for. `A` and `A'` are different unknowns,
+/// fixed columns are known and count as coefficients. Elements of array
+/// columns count as the array.
+fn degrees<'a, T>(
+    expr: &'a AlgebraicExpression<T>,
+    pil: &'a Analyzed<T>,
+    depth: usize,
+) -> HashMap<Column<'a>, usize> {
+    match expr {
+        AlgebraicExpression::Reference(reference) => {
+            let name = array_name(&reference.name);
+            match pil.intermediate_columns.get(name) {
+                Some((_, expressions)) if depth < MAX_INTERMEDIATE_DEPTH => {
+                    let mut result = HashMap::new();
+                    for expr in expressions {
+                        merge_max(&mut result, degrees(expr, pil, depth + 1));
+                    }
+                    if reference.next {
+                        result = result
+                            .into_iter()
+                            .map(|((name, _), degree)| ((name, true), degree))
+                            .collect();
+                    }
+                    result
+                }
+                Some(_) => HashMap::new(),
+                None if reference.poly_id.ptype == PolynomialType::Committed => {
+                    HashMap::from([((name, reference.next), 1)])
+                }
+                None => HashMap::new(),
+            }
+        }
+        AlgebraicExpression::BinaryOperation(AlgebraicBinaryOperation { left, op, right }) => {
+            let mut left = degrees(left, pil, depth);
+            let right = degrees(right, pil, depth);
+            match op {
+                AlgebraicBinaryOperator::Add | AlgebraicBinaryOperator::Sub => {
+                    merge_max(&mut left, right);
+                }
+                // A column times a constant keeps its degree, times a column
+                // it is only determined where the other column is not zero.
+                AlgebraicBinaryOperator::Mul if left.is_empty() => return right,
+                AlgebraicBinaryOperator::Mul if right.is_empty() => {}
+                AlgebraicBinaryOperator::Mul => {
+                    merge_max(&mut left, right);
+                    left.values_mut().for_each(|degree| *degree = 2);
+                }
+                AlgebraicBinaryOperator::Pow => {
+                    left.values_mut().for_each(|degree| *degree = 2);
+                }
+            }
+            left
+        }
+        AlgebraicExpression::UnaryOperation(AlgebraicUnaryOperation { expr, .. }) => {
+            degrees(expr, pil, depth)
+        }
+        _ => HashMap::new(),
+    }
+}
+
+fn merge_max<'a>(into: &mut HashMap<Column<'a>, usize>, other: HashMap<Column<'a>, usize>) {
+    for (column, degree) in other {
+        let entry = into.entry(column).or_default();
+        *entry = (*entry).max(degree);
+    }
+}
+
+/// Elements of array columns are referenced as `name[i]`.
+pub(crate) fn array_name(reference: &str) -> &str {
+    reference
+        .split_once('[')
+        .map_or(reference, |(name, _)| name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use powdr_number::GoldilocksField;
+
+    /// The columns flagged in a PIL document.
+    fn flagged(text: &str) -> Vec<String> {
+        let uri = Url::parse("file:///test.pil").unwrap();
+        let result = crate::parser::parse::<GoldilocksField>(text, &uri);
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+        underconstrained_columns(text, &result.analyzed)
+            .into_iter()
+            .map(|diagnostic| diagnostic.message.split('`').nth(1).unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn only_constant_coefficients_determine_a_column() {
+        let text = "namespace Main(8);\n\
+            col witness sel, x, y, z;\n\
+            sel * (1 - sel) = 0;\n\
+            sel * (x - y) = 0;\n\
+            y = 3;\n\
+            3 * z + 1 = 0;\n";
+        assert_eq!(flagged(text), vec!["Main::sel", "Main::x"]);
+    }
+
+    #[test]
+    fn right_hand_sides_of_lookups_and_permutations_are_not_determined() {
+        let text = "namespace Main(8);\n\
+            col witness a, b, c;\n\
+            a = 1;\n\
+            [a] in [b];\n\
+            [a] is [c];\n";
+        assert_eq!(flagged(text), vec!["Main::b", "Main::c"]);
+    }
+
+    #[test]
+    fn next_row_and_fixed_columns_are_told_apart() {
+        let text = "namespace Main(8);\n\
+            col fixed FIRST = [1] + [0]*;\n\
+            col fixed write = [1, 0]*;\n\
+            col witness A, X;\n\
+            A' = write * X + (1 - write) * A;\n\
+            FIRST * A = 0;\n\
+            X = 5;\n";
+        assert!(flagged(text).is_empty());
+    }
+
+    #[test]
+    fn lowered_register_updates_are_determined() {
+        let text = "machine Main with degree: 8 {\n\
+            reg pc[@pc];\n\
+            reg X[<=];\n\
+            reg A;\n\
+            \n\
+            function main {\n\
+            A <=X= 3;\n\
+            A <=X= A + 1;\n\
+            return;\n\
+            }\n\
+            }\n";
+        let uri = Url::parse("file:///test.asm").unwrap();
+        let result = crate::parser::parse::<GoldilocksField>(text, &uri);
+        let AnalyzedDoc::ASM(asm) = result.analyzed else {
+            unreachable!()
+        };
+        let lowered = crate::compile::lower::<GoldilocksField>(&uri, asm);
+        if let Err(errors) = &lowered {
+            panic!("{errors:?}");
+        }
+
+        let flagged = lowered_underconstrained_columns(text, &lowered);
+        assert!(flagged.is_empty(), "{flagged:?}");
+    }
+}
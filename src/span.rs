@@ -11,3 +11,63 @@ impl HasSpan for Span {
         self.clone()
     }
 }
+
+/// Identifiers, string literals and single punctuation characters of the text.
+/// Comments are skipped and strings are single tokens, so braces inside them
+/// never open or close a block.
+pub(crate) fn code_tokens(text: &str) -> Vec<Span> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+
+    while let Some((pos, c)) = chars.next() {
+        let rest = &text[pos..];
+        if rest.starts_with("//") {
+            let end = rest.find('\n').map_or(text.len(), |end| pos + end);
+            while chars.next_if(|(p, _)| *p < end).is_some() {}
+        } else if rest.starts_with("/*") {
+            let end = rest[2..].find("*/").map_or(text.len(), |end| pos + end + 4);
+            while chars.next_if(|(p, _)| *p < end).is_some() {}
+        } else if c == '"' {
+            let mut end = text.len();
+            while let Some((p, c)) = chars.next() {
+                match c {
+                    '\\' => {
+                        chars.next();
+                    }
+                    '"' => {
+                        end = p + 1;
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            tokens.push(pos..end);
+        } else if is_word(c) {
+            let mut end = pos + c.len_utf8();
+            while let Some((p, c)) = chars.next_if(|(_, c)| is_word(*c)) {
+                end = p + c.len_utf8();
+            }
+            tokens.push(pos..end);
+        } else if !c.is_whitespace() {
+            tokens.push(pos..pos + c.len_utf8());
+        }
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_tokens_skip_comments() {
+        let text = "a /* { */ \"}\" // {\nb";
+        let tokens: Vec<_> = code_tokens(text)
+            .into_iter()
+            .map(|span| &text[span])
+            .collect();
+        assert_eq!(tokens, vec!["a", "\"}\"", "b"]);
+    }
+}
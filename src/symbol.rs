@@ -1,20 +1,41 @@
 use crate::span::Span;
 use rust_lapper::{Interval, Lapper};
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 
 pub type SymbolId = u32;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum SymbolKind {
     Machine,
-    Callable,
+    Instruction,
+    Operation,
+    Function,
     Register,
+    /// An instance of another machine declared inside a machine.
+    Submachine,
+    Module,
+    /// A `use` alias.
+    Import,
+    /// A module level `let`.
+    Constant,
+    FixedColumn,
+    WitnessColumn,
+    /// The `instance.operation` target of a link.
+    Link,
+    Label,
+    Enum,
+    EnumVariant,
+    Struct,
+    Trait,
+    Namespace,
     Definition,
     Public,
     Intermediate,
     TraitImpl,
 }
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Symbol {
     pub kind: SymbolKind,
     pub span: Span,
@@ -22,36 +43,130 @@ pub struct Symbol {
     pub details: SymbolDetails,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum SymbolDetails {
-    Machine { degree: Option<DegreeInfo> },
-    Register { type_info: String },
-    Callable { inputs: String, outputs: String },
+    Machine {
+        degree: Option<DegreeInfo>,
+        summary: MachineSummary,
+    },
+    Register {
+        type_info: String,
+    },
+    /// Instructions, operations and functions.
+    Callable {
+        inputs: String,
+        outputs: String,
+    },
+    Submachine {
+        machine_type: String,
+        arguments: String,
+    },
+    Module {
+        path: String,
+    },
+    Import {
+        target: String,
+    },
+    Constant {
+        type_info: Option<String>,
+        value: Option<String>,
+    },
+    Column {
+        declaration: String,
+    },
+    Link {
+        target: String,
+        permutation: bool,
+    },
+    Label {
+        function: String,
+    },
+    /// PIL symbols, `declaration` is how hover shows them, e.g. `let f: int -> fe`.
+    Declaration {
+        declaration: String,
+        type_info: Option<String>,
+        length: Option<u64>,
+        stage: Option<u32>,
+        /// Defining expression of constants.
+        value: Option<String>,
+    },
     Definition,
     Public,
     Intermediate,
     TraitImpl,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UseKind {
+    Declaration,
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SymbolUse {
+    pub name: String,
+    pub span: Span,
+    pub kind: UseKind,
+    /// Path of the machine or module an asm use is in. PIL names are
+    /// absolute and their uses have no scope.
+    pub scope: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DegreeInfo {
-    pub min: Option<u64>,
-    pub max: Option<u64>,
+    pub min: Option<String>,
+    pub max: Option<String>,
 }
 
 impl From<powdr_ast::asm_analysis::MachineDegree> for DegreeInfo {
     fn from(degree: powdr_ast::asm_analysis::MachineDegree) -> Self {
         DegreeInfo {
-            min: Some(8 as u64), //TODO evaluate expr
-            max: Some(10 as u64),
+            min: degree.min.map(|e| e.to_string()),
+            max: degree.max.map(|e| e.to_string()),
         }
     }
 }
 
+/// Structure of a machine as shown on hover.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MachineSummary {
+    pub params: String,
+    pub latch: Option<String>,
+    pub operation_id: Option<String>,
+    pub call_selectors: Option<String>,
+    pub registers: usize,
+    pub instructions: usize,
+    pub operations: usize,
+    /// `(instance, machine type)` pairs.
+    pub submachines: Vec<(String, String)>,
+    /// `instance.callable` targets of the machine's links.
+    pub links: Vec<String>,
+}
+
 #[derive(Debug, Clone)]
 pub struct SemanticIndex {
     pub symbols: HashMap<SymbolId, Symbol>,
     pub range_index: Lapper<usize, SymbolId>,
+    pub uses: Vec<SymbolUse>,
+    /// Position in `uses` of the first use at each span.
+    use_spans: HashMap<Span, usize>,
+    /// The declaring symbol of each symbol added at a use.
+    pub declarations: HashMap<SymbolId, SymbolId>,
+    /// Doc comments of declarations, rendered as Markdown, by symbol id.
+    pub docs: HashMap<SymbolId, String>,
+}
+
+/// The range index is derived from the symbols and not compared.
+impl PartialEq for SemanticIndex {
+    fn eq(&self, other: &Self) -> bool {
+        self.symbols == other.symbols
+            && self.uses == other.uses
+            && self.declarations == other.declarations
+            && self.docs == other.docs
+    }
 }
 
 impl SemanticIndex {
@@ -59,6 +174,10 @@ impl SemanticIndex {
         Self {
             symbols: HashMap::new(),
             range_index: Lapper::new(vec![]),
+            uses: Vec::new(),
+            use_spans: HashMap::new(),
+            declarations: HashMap::new(),
+            docs: HashMap::new(),
         }
     }
 
@@ -73,11 +192,101 @@ impl SemanticIndex {
         id
     }
 
+    /// Adds `symbol` at a use of the symbol `declaration`.
+    pub fn add_occurrence(&mut self, symbol: Symbol, declaration: SymbolId) -> SymbolId {
+        let id = self.add_symbol(symbol);
+        self.declarations.insert(id, declaration);
+        id
+    }
+
+    /// The symbol declaring `id`, `id` itself at a declaration.
+    pub fn declaration_of(&self, id: SymbolId) -> SymbolId {
+        self.declarations.get(&id).copied().unwrap_or(id)
+    }
+
+    /// Adds the symbols and uses of `other`, an index of a part of the document.
+    pub fn extend(&mut self, other: &SemanticIndex) {
+        // Ids are assigned in order, so those of `other` move up by `base`.
+        let base = self.symbols.len() as SymbolId;
+        let mut ids: Vec<_> = other.symbols.keys().collect();
+        ids.sort();
+        for id in ids {
+            self.add_symbol(other.symbols[id].clone());
+        }
+        for (id, declaration) in &other.declarations {
+            self.declarations.insert(base + id, base + declaration);
+        }
+        for (id, doc) in &other.docs {
+            self.docs.insert(base + id, doc.clone());
+        }
+        for symbol_use in &other.uses {
+            self.add_use(symbol_use.clone());
+        }
+    }
+
     pub fn find_symbol_at_position(&self, offset: usize) -> Option<&Symbol> {
+        self.find_symbol_id_at_position(offset)
+            .and_then(|id| self.symbols.get(&id))
+    }
+
+    pub fn find_symbol_id_at_position(&self, offset: usize) -> Option<SymbolId> {
         self.range_index
             .find(offset, offset + 1)
             .next()
-            .and_then(|interval| self.symbols.get(&interval.val))
+            .map(|interval| interval.val)
+    }
+
+    pub fn add_use(&mut self, symbol_use: SymbolUse) {
+        self.use_spans
+            .entry(symbol_use.span.clone())
+            .or_insert(self.uses.len());
+        self.uses.push(symbol_use);
+    }
+
+    /// Puts the uses recorded since `first_use` that are not yet in a scope
+    /// into `scope`.
+    pub fn scope_uses(&mut self, first_use: usize, scope: &str) {
+        for symbol_use in &mut self.uses[first_use..] {
+            if symbol_use.scope.is_empty() {
+                symbol_use.scope = scope.to_string();
+            }
+        }
+    }
+
+    pub fn add_doc(&mut self, id: SymbolId, doc: String) {
+        self.docs.insert(id, doc);
+    }
+
+    /// The doc comment of the declaration of `id`.
+    pub fn doc(&self, id: SymbolId) -> Option<&str> {
+        self.docs.get(&self.declaration_of(id)).map(String::as_str)
+    }
+
+    pub fn use_kind_at(&self, span: &Span) -> Option<UseKind> {
+        self.use_spans.get(span).map(|&pos| self.uses[pos].kind)
+    }
+
+    /// The occurrences of the symbol `id` refers to, found through the
+    /// declaration each of them is linked to, so that equal names declared in
+    /// different scopes stay apart.
+    pub fn occurrences_of(&self, id: SymbolId) -> impl Iterator<Item = &Symbol> {
+        let declaration = self.symbols.get(&self.declaration_of(id));
+        // Machines are declared under their full and their short name at the same span.
+        let declarations: HashSet<SymbolId> = self
+            .symbols
+            .iter()
+            .filter(|(other, symbol)| {
+                self.declaration_of(**other) == **other
+                    && declaration.is_some_and(|declaration| {
+                        symbol.span == declaration.span && symbol.kind == declaration.kind
+                    })
+            })
+            .map(|(other, _)| *other)
+            .collect();
+        self.symbols
+            .iter()
+            .filter(move |(other, _)| declarations.contains(&self.declaration_of(**other)))
+            .map(|(_, symbol)| symbol)
     }
 
     // pub fn get_all_ranges(&self) -> Vec<(Span, &Symbol)> {
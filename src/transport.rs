@@ -0,0 +1,30 @@
+use std::path::PathBuf;
+
+pub const USAGE: &str =
+    "Usage: powdr-lsp [--listen <port> | --connect <host:port> | --pipe <path>]";
+
+/// How the server talks to the editor.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Transport {
+    Stdio,
+    /// Accepts any number of editors on a local TCP port, all sharing one index.
+    Listen(u16),
+    /// Connects to an editor listening on a TCP address.
+    Connect(String),
+    /// Connects to a Unix domain socket, or a named pipe on Windows.
+    Pipe(PathBuf),
+}
+
+pub fn parse_transport(args: &[String]) -> Result<Transport, String> {
+    match args {
+        [] => Ok(Transport::Stdio),
+        [flag] if flag == "--stdio" => Ok(Transport::Stdio),
+        [flag, port] if flag == "--listen" => port
+            .parse()
+            .map(Transport::Listen)
+            .map_err(|_| format!("Invalid port: {port}")),
+        [flag, address] if flag == "--connect" => Ok(Transport::Connect(address.clone())),
+        [flag, path] if flag == "--pipe" => Ok(Transport::Pipe(PathBuf::from(path))),
+        _ => Err(USAGE.to_string()),
+    }
+}
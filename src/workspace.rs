@@ -0,0 +1,177 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use ignore::WalkBuilder;
+use powdr_number::FieldElement;
+use serde::Deserialize;
+use tower_lsp::lsp_types::Url;
+use tracing::warn;
+
+use crate::analyzer::build_semantic_index;
+use crate::index_cache::IndexCache;
+use crate::project::ParsedDocument;
+use crate::symbol::SemanticIndex;
+
+/// Section of the client configuration read for every workspace folder.
+pub const CONFIGURATION_SECTION: &str = "powdr";
+
+/// Per-folder settings, from the `powdr` configuration section scoped to the folder.
+///
+/// The server analyzes every folder over the Goldilocks field, with one
+/// project index that requests scope to the owning folder. There are no
+/// per-folder fields or backends.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FolderConfig {
+    /// Directory of a powdr std checkout indexed alongside the folder, for
+    /// navigation. The importer resolves `std` against the std powdr was
+    /// built with and takes no path for it.
+    pub std_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WorkspaceFolder {
+    pub uri: Url,
+    pub name: String,
+    pub config: FolderConfig,
+}
+
+impl WorkspaceFolder {
+    pub fn new(uri: Url, name: String) -> Self {
+        Self {
+            uri,
+            name,
+            config: FolderConfig::default(),
+        }
+    }
+
+    /// Used for clients that only send a `rootUri`.
+    pub fn from_root(uri: Url) -> Self {
+        let name = uri
+            .path_segments()
+            .and_then(|mut segments| segments.rfind(|s| !s.is_empty()))
+            .unwrap_or("root")
+            .to_string();
+        Self::new(uri, name)
+    }
+
+    /// The directories indexed for this folder: the folder itself and its std path.
+    pub fn roots(&self) -> Vec<Url> {
+        let std = self
+            .config
+            .std_path
+            .as_ref()
+            .and_then(|path| Url::from_directory_path(path).ok());
+        std::iter::once(self.uri.clone()).chain(std).collect()
+    }
+
+    pub fn contains(&self, uri: &Url) -> bool {
+        self.roots().iter().any(|root| is_below(uri, root))
+    }
+}
+
+/// The innermost folder containing the document, folders can be nested.
+pub fn owning_folder<'a>(folders: &'a [WorkspaceFolder], uri: &Url) -> Option<&'a WorkspaceFolder> {
+    folders
+        .iter()
+        .filter(|folder| is_below(uri, &folder.uri))
+        .max_by_key(|folder| folder.uri.as_str().len())
+        .or_else(|| folders.iter().find(|folder| folder.contains(uri)))
+}
+
+fn is_below(uri: &Url, root: &Url) -> bool {
+    let root = root.as_str().trim_end_matches('/');
+    uri.as_str()
+        .strip_prefix(root)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+pub fn is_source_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "pil" || extension == "asm")
+}
+
+/// Recursively collects the `.asm` and `.pil` files below `dir`, skipping
+/// anything excluded by `.gitignore` files. Entries that cannot be read are
+/// logged and skipped.
+pub fn find_source_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    // Fail early if the root itself is missing or unreadable.
+    fs::read_dir(dir)?;
+
+    let files = WalkBuilder::new(dir)
+        .require_git(false)
+        .build()
+        .filter_map(|entry| match entry {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                warn!(error = %e, "skipping unreadable entry");
+                None
+            }
+        })
+        .filter(|entry| entry.file_type().is_some_and(|ty| ty.is_file()))
+        .map(|entry| entry.into_path())
+        .filter(|path| is_source_file(path))
+        .collect();
+
+    Ok(files)
+}
+
+/// A workspace file, either freshly analyzed or known only from the index cache.
+pub enum LoadedDocument {
+    Analyzed(ParsedDocument),
+    Cached(SemanticIndex),
+}
+
+/// Reads, parses and indexes a file from disk.
+pub fn load_document<T: FieldElement>(path: &Path) -> io::Result<(Url, ParsedDocument)> {
+    let (path, uri, text) = read_document(path)?;
+    Ok((uri, analyze::<T>(&path, &uri, text, None)))
+}
+
+/// Like `load_document`, but answers from the index cache when the file is unchanged
+/// and stores fresh analyses in it.
+pub fn load_document_cached<T: FieldElement>(
+    path: &Path,
+    cache: &IndexCache,
+) -> io::Result<(Url, LoadedDocument)> {
+    let (path, uri, text) = read_document(path)?;
+    match cache.load(&path, &text) {
+        Some(index) => Ok((uri, LoadedDocument::Cached(index))),
+        None => {
+            let doc = analyze::<T>(&path, &uri, text, Some(cache));
+            Ok((uri, LoadedDocument::Analyzed(doc)))
+        }
+    }
+}
+
+fn read_document(path: &Path) -> io::Result<(PathBuf, Url, String)> {
+    let path = path.canonicalize()?;
+    let text = fs::read_to_string(&path)?;
+    let uri = Url::from_file_path(&path)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid file path"))?;
+    Ok((path, uri, text))
+}
+
+fn analyze<T: FieldElement>(
+    path: &Path,
+    uri: &Url,
+    text: String,
+    cache: Option<&IndexCache>,
+) -> ParsedDocument {
+    let result = crate::parser::parse::<T>(&text, uri);
+    let semantic_index = build_semantic_index(&result.analyzed, result.parsed.as_ref(), &text);
+
+    if let Some(cache) = cache {
+        cache.store(path, &text, &result.analyzed, &semantic_index);
+    }
+
+    ParsedDocument::new(
+        uri,
+        text.into(),
+        0,
+        &result.analyzed,
+        Arc::new(semantic_index),
+    )
+}
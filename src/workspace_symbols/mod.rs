@@ -0,0 +1,167 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::Arc;
+
+use crate::parser::convert_position;
+use crate::project::ProjectCache;
+use crate::symbol::{Symbol, SymbolKind, UseKind};
+use crate::workspace::{WorkspaceFolder, owning_folder};
+use tower_lsp::lsp_types::*;
+
+pub struct WorkspaceSymbolProvider<'a> {
+    cache: &'a ProjectCache,
+    folders: &'a [WorkspaceFolder],
+}
+
+/// The symbols of one document matching a query, taken out of the project
+/// cache so that positions can be computed without holding it.
+pub struct DocumentMatches {
+    uri: Url,
+    /// Indexes loaded from the on-disk cache come without text.
+    text: Option<Arc<str>>,
+    container_name: Option<String>,
+    symbols: Vec<Symbol>,
+}
+
+impl<'a> WorkspaceSymbolProvider<'a> {
+    pub fn new(cache: &'a ProjectCache, folders: &'a [WorkspaceFolder]) -> Self {
+        Self { cache, folders }
+    }
+
+    /// Symbols whose name contains `query`, ignoring case. Each one is reported
+    /// once per document, at its declaration or else its first occurrence,
+    /// with the owning workspace folder as container.
+    pub fn get_matches(&self, query: &str) -> Vec<DocumentMatches> {
+        let query = query.to_lowercase();
+        let mut matches = Vec::new();
+
+        for (uri, text, index) in self.cache.indexes() {
+            let mut chosen: BTreeMap<(&str, u8), (bool, &Symbol)> = BTreeMap::new();
+            for symbol in index.symbols.values() {
+                if !symbol.name.to_lowercase().contains(&query) {
+                    continue;
+                }
+                let declaration = index.use_kind_at(&symbol.span) == Some(UseKind::Declaration);
+                chosen
+                    .entry((symbol.name.as_str(), kind_order(&symbol.kind)))
+                    .and_modify(|current| {
+                        // Declarations first, then the earliest occurrence.
+                        if (!declaration, symbol.span.start) < (!current.0, current.1.span.start) {
+                            *current = (declaration, symbol);
+                        }
+                    })
+                    .or_insert((declaration, symbol));
+            }
+            if chosen.is_empty() {
+                continue;
+            }
+
+            matches.push(DocumentMatches {
+                uri: uri.clone(),
+                text: text.cloned(),
+                container_name: owning_folder(self.folders, uri).map(|folder| folder.name.clone()),
+                symbols: chosen
+                    .into_values()
+                    .map(|(_, symbol)| symbol.clone())
+                    .collect(),
+            });
+        }
+
+        matches
+    }
+}
+
+/// Locates the matches, reading the documents that have no text in memory.
+pub fn symbol_information(matches: Vec<DocumentMatches>) -> Vec<SymbolInformation> {
+    let mut symbols = Vec::new();
+
+    for document in matches {
+        let text = match document.text {
+            Some(text) => text,
+            None => match document
+                .uri
+                .to_file_path()
+                .ok()
+                .and_then(|path| fs::read_to_string(path).ok())
+            {
+                Some(text) => text.into(),
+                None => continue,
+            },
+        };
+
+        for symbol in document.symbols {
+            if symbol.span.end > text.len() {
+                continue;
+            }
+            #[allow(deprecated)]
+            symbols.push(SymbolInformation {
+                name: symbol.name,
+                kind: lsp_kind(&symbol.kind),
+                tags: None,
+                deprecated: None,
+                location: Location::new(
+                    document.uri.clone(),
+                    Range::new(
+                        convert_position(symbol.span.start, &text),
+                        convert_position(symbol.span.end, &text),
+                    ),
+                ),
+                container_name: document.container_name.clone(),
+            });
+        }
+    }
+
+    symbols.sort_by(|a, b| {
+        (a.name.as_str(), a.location.uri.as_str()).cmp(&(b.name.as_str(), b.location.uri.as_str()))
+    });
+    symbols
+}
+
+fn kind_order(kind: &SymbolKind) -> u8 {
+    match kind {
+        SymbolKind::Machine => 0,
+        SymbolKind::Instruction => 1,
+        SymbolKind::Operation => 2,
+        SymbolKind::Function => 3,
+        SymbolKind::Register => 4,
+        SymbolKind::Submachine => 5,
+        SymbolKind::Module => 6,
+        SymbolKind::Import => 7,
+        SymbolKind::Constant => 8,
+        SymbolKind::FixedColumn => 9,
+        SymbolKind::WitnessColumn => 10,
+        SymbolKind::Link => 11,
+        SymbolKind::Label => 12,
+        SymbolKind::Enum => 13,
+        SymbolKind::EnumVariant => 14,
+        SymbolKind::Struct => 15,
+        SymbolKind::Trait => 16,
+        SymbolKind::Namespace => 17,
+        SymbolKind::Definition => 18,
+        SymbolKind::Public => 19,
+        SymbolKind::Intermediate => 20,
+        SymbolKind::TraitImpl => 21,
+    }
+}
+
+fn lsp_kind(kind: &SymbolKind) -> tower_lsp::lsp_types::SymbolKind {
+    use tower_lsp::lsp_types::SymbolKind as Lsp;
+    match kind {
+        SymbolKind::Machine => Lsp::CLASS,
+        SymbolKind::Instruction | SymbolKind::Operation => Lsp::METHOD,
+        SymbolKind::Function => Lsp::FUNCTION,
+        SymbolKind::Register | SymbolKind::Submachine => Lsp::FIELD,
+        SymbolKind::Module => Lsp::MODULE,
+        SymbolKind::Import => Lsp::NAMESPACE,
+        SymbolKind::Constant | SymbolKind::Public => Lsp::CONSTANT,
+        SymbolKind::FixedColumn | SymbolKind::WitnessColumn => Lsp::PROPERTY,
+        SymbolKind::Link => Lsp::EVENT,
+        SymbolKind::Label => Lsp::KEY,
+        SymbolKind::Definition | SymbolKind::Intermediate => Lsp::VARIABLE,
+        SymbolKind::Enum => Lsp::ENUM,
+        SymbolKind::EnumVariant => Lsp::ENUM_MEMBER,
+        SymbolKind::Struct => Lsp::STRUCT,
+        SymbolKind::Trait | SymbolKind::TraitImpl => Lsp::INTERFACE,
+        SymbolKind::Namespace => Lsp::NAMESPACE,
+    }
+}